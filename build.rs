@@ -1,3 +1,5 @@
 fn main() {
-    embed_resource::compile("app.rc", embed_resource::NONE);
+    if std::env::var("CARGO_CFG_TARGET_OS").as_deref() == Ok("windows") {
+        embed_resource::compile("app.rc", embed_resource::NONE);
+    }
 }
@@ -0,0 +1,135 @@
+use super::AudioSource;
+use std::f64::consts::TAU;
+use std::fs;
+use std::io;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// hands out samples no faster than they would play, like a real device would
+struct Clock {
+    started: Option<Instant>,
+    produced: u64,
+    rate: u32,
+}
+
+impl Clock {
+    fn new(rate: u32) -> Self {
+        Clock { started: None, produced: 0, rate }
+    }
+
+    // frames that may be produced now, waiting until there is at least one
+    fn take(&mut self, max: usize) -> usize {
+        let started = *self.started.get_or_insert_with(Instant::now);
+        loop {
+            let due = (started.elapsed().as_secs_f64() * self.rate as f64) as u64;
+            if due > self.produced {
+                let n = ((due - self.produced) as usize).min(max);
+                self.produced += n as u64;
+                return n;
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+}
+
+/// Endless test tone.
+pub struct SineSource {
+    freq: f64,
+    channels: u16,
+    phase: u64,
+    clock: Clock,
+}
+
+impl SineSource {
+    pub fn new(freq: f64, sample_rate: u32, channels: u16) -> Self {
+        SineSource { freq, channels, phase: 0, clock: Clock::new(sample_rate) }
+    }
+}
+
+impl AudioSource for SineSource {
+    fn sample_rate(&self) -> u32 {
+        self.clock.rate
+    }
+
+    fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    fn read(&mut self, buf: &mut [i16]) -> io::Result<usize> {
+        let channels = self.channels as usize;
+        let frames = self.clock.take(buf.len() / channels);
+        for frame in buf[..frames * channels].chunks_exact_mut(channels) {
+            let t = self.phase as f64 / self.clock.rate as f64;
+            let s = ((TAU * self.freq * t).sin() * i16::MAX as f64 * 0.5) as i16;
+            frame.fill(s);
+            self.phase += 1;
+        }
+        Ok(frames * channels)
+    }
+}
+
+/// Loops a 16-bit PCM WAV file forever.
+pub struct WavSource {
+    samples: Vec<i16>,
+    channels: u16,
+    pos: usize,
+    clock: Clock,
+}
+
+impl WavSource {
+    pub fn open(path: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let data = fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+        if data.len() < 12 || &data[0..4] != b"RIFF" || &data[8..12] != b"WAVE" {
+            return Err(format!("{} is not a WAV file", path).into());
+        }
+
+        let mut format = None;
+        let mut samples = None;
+        let mut chunks = &data[12..];
+        while chunks.len() >= 8 {
+            let id = &chunks[0..4];
+            let len = u32::from_le_bytes([chunks[4], chunks[5], chunks[6], chunks[7]]) as usize;
+            let body = chunks.get(8..8 + len).ok_or_else(|| format!("{} is truncated", path))?;
+            match id {
+                b"fmt " if body.len() >= 16 => {
+                    let tag = u16::from_le_bytes([body[0], body[1]]);
+                    let channels = u16::from_le_bytes([body[2], body[3]]);
+                    let rate = u32::from_le_bytes([body[4], body[5], body[6], body[7]]);
+                    let bits = u16::from_le_bytes([body[14], body[15]]);
+                    if tag != 1 || bits != 16 || channels == 0 {
+                        return Err(format!("{} must be 16-bit PCM", path).into());
+                    }
+                    format = Some((channels, rate));
+                },
+                b"data" => samples = Some(body.chunks_exact(2).map(|b| i16::from_le_bytes([b[0], b[1]])).collect::<Vec<_>>()),
+                _ => {},
+            }
+            // chunks are padded to an even length
+            chunks = chunks.get(8 + len + (len & 1)..).unwrap_or(&[]);
+        }
+
+        let (channels, rate) = format.ok_or_else(|| format!("{} has no fmt chunk", path))?;
+        let samples = samples.filter(|s| s.len() >= channels as usize).ok_or_else(|| format!("{} has no audio", path))?;
+        Ok(WavSource { samples, channels, pos: 0, clock: Clock::new(rate) })
+    }
+}
+
+impl AudioSource for WavSource {
+    fn sample_rate(&self) -> u32 {
+        self.clock.rate
+    }
+
+    fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    fn read(&mut self, buf: &mut [i16]) -> io::Result<usize> {
+        let channels = self.channels as usize;
+        let frames = self.clock.take(buf.len() / channels);
+        for s in &mut buf[..frames * channels] {
+            *s = self.samples[self.pos];
+            self.pos = (self.pos + 1) % (self.samples.len() / channels * channels);
+        }
+        Ok(frames * channels)
+    }
+}
@@ -0,0 +1,165 @@
+mod generator;
+#[cfg(target_os = "linux")]
+mod pulse;
+#[cfg(windows)]
+mod wasapi;
+
+use crate::nut::{StreamKind, TIME_BASE};
+use crate::writer::{FrameSender, MediaClock};
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Instant;
+
+pub use generator::{SineSource, WavSource};
+#[cfg(target_os = "linux")]
+pub use pulse::PulseSource;
+#[cfg(windows)]
+pub use wasapi::WasapiSource;
+
+pub trait AudioSource: Send {
+    fn sample_rate(&self) -> u32;
+
+    fn channels(&self) -> u16;
+
+    /// Blocks until some interleaved s16 samples are ready and returns how many were written, 0 once the source is done.
+    /// Live sources keep producing (silence if need be) so the encoder never waits on them.
+    fn read(&mut self, buf: &mut [i16]) -> io::Result<usize>;
+}
+
+/// `desktop`, `mic`, `sine[:freq]` or `wav:path`.
+pub fn open(spec: &str) -> Result<Box<dyn AudioSource>, Box<dyn std::error::Error>> {
+    let (kind, arg) = spec.split_once(':').unwrap_or((spec, ""));
+    match kind {
+        #[cfg(windows)]
+        "desktop" => Ok(Box::new(WasapiSource::loopback()?)),
+        #[cfg(windows)]
+        "mic" => Ok(Box::new(WasapiSource::microphone()?)),
+        #[cfg(target_os = "linux")]
+        "desktop" => Ok(Box::new(PulseSource::new("@DEFAULT_MONITOR@")?)),
+        #[cfg(target_os = "linux")]
+        "mic" => Ok(Box::new(PulseSource::new("@DEFAULT_SOURCE@")?)),
+        "sine" => {
+            let freq = if arg.is_empty() { 440. } else { arg.parse().map_err(|_| format!("Bad sine frequency \"{}\"", arg))? };
+            Ok(Box::new(SineSource::new(freq, 48000, 2)))
+        },
+        "wav" => Ok(Box::new(WavSource::open(arg)?)),
+        _ => Err(format!("Unknown audio source \"{}\"", spec).into()),
+    }
+}
+
+// a stamp this far off the sample count means samples went missing (or piled up) and the
+// count can't be trusted anymore
+const RESYNC_US: u64 = 50_000;
+
+struct Sink {
+    sender: FrameSender,
+    stream: usize,
+    clock: Arc<MediaClock>,
+    // the stamp of the first packet since (re)syncing and how many sample frames followed it
+    anchor: Option<(u64, u64)>,
+}
+
+/// Pumps one source for the whole run at the given volume. Every encoder session connects it
+/// to its writer, whatever is captured while nothing is connected or recording is paused is dropped.
+/// Each monitor being recorded connects in its own slot and gets every sample.
+pub struct AudioInput {
+    pub sample_rate: u32,
+    pub channels: u16,
+    sinks: Arc<Mutex<Vec<Option<Sink>>>>,
+}
+
+impl AudioInput {
+    pub fn start(mut source: Box<dyn AudioSource>, volume: f32) -> Self {
+        let sinks: Arc<Mutex<Vec<Option<Sink>>>> = Arc::new(Mutex::new(Vec::new()));
+        let input = AudioInput { sample_rate: source.sample_rate(), channels: source.channels(), sinks: sinks.clone() };
+        let rate = source.sample_rate() as u64;
+        let channels = source.channels() as usize;
+
+        thread::spawn(move || {
+            let mut buf = vec![0i16; 4096];
+            loop {
+                let n = match source.read(&mut buf) {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => n,
+                };
+                let arrived = Instant::now();
+
+                let mut sinks = sinks.lock().unwrap();
+                if sinks.iter().all(Option::is_none) {
+                    continue;
+                }
+                let mut bytes = Vec::with_capacity(n * 2);
+                for &s in &buf[..n] {
+                    let s = (s as f32 * volume).clamp(i16::MIN as f32, i16::MAX as f32) as i16;
+                    bytes.extend_from_slice(&s.to_le_bytes());
+                }
+
+                let frames = (n / channels) as u64;
+                for slot in sinks.iter_mut() {
+                    let Some(connected) = slot.as_mut() else {
+                        continue;
+                    };
+                    let Some(pts) = connected.stamp(arrived, frames, rate) else {
+                        continue;
+                    };
+                    if !connected.sender.send_audio(connected.stream, pts, bytes.clone()) {
+                        *slot = None;
+                    }
+                }
+            }
+        });
+
+        input
+    }
+
+    /// Feeds this input into `stream` of the next encoder session in `slot`, stamped by `clock`.
+    pub fn connect(&self, slot: usize, sender: FrameSender, stream: usize, clock: Arc<MediaClock>) {
+        let mut sinks = self.sinks.lock().unwrap();
+        if sinks.len() <= slot {
+            sinks.resize_with(slot + 1, || None);
+        }
+        sinks[slot] = Some(Sink { sender, stream, clock, anchor: None });
+    }
+
+    pub fn stream_kind(&self) -> StreamKind {
+        StreamKind::Audio { sample_rate: self.sample_rate, channels: self.channels }
+    }
+}
+
+impl Sink {
+    // when `frames` sample frames that came in at `arrived` start, None to drop them
+    fn stamp(&mut self, arrived: Instant, frames: u64, rate: u64) -> Option<u64> {
+        if self.clock.is_paused() {
+            self.anchor = None;
+            return None;
+        }
+
+        // samples come in a steady stream, their count keeps better time than the
+        // clock reading at arrival, which carries the source's buffering jitter
+        let duration = frames * TIME_BASE / rate;
+        let measured = self.clock.at(arrived).saturating_sub(duration);
+        let pts = match self.anchor {
+            Some((start, counted)) => {
+                let expected = start + counted * TIME_BASE / rate;
+                if measured > expected + RESYNC_US {
+                    self.anchor = Some((measured, 0));
+                    measured
+                } else if measured + RESYNC_US < expected {
+                    // the source is ahead of real time, let the clock catch up
+                    return None;
+                } else {
+                    expected
+                }
+            },
+            None => {
+                self.anchor = Some((measured, 0));
+                measured
+            },
+        };
+        if let Some((_, counted)) = self.anchor.as_mut() {
+            *counted += frames;
+        }
+        Some(pts)
+    }
+}
@@ -0,0 +1,68 @@
+use super::AudioSource;
+use std::io::{self, Read};
+use std::process::{Child, ChildStdout, Command, Stdio};
+
+const RATE: u32 = 48000;
+const CHANNELS: u16 = 2;
+
+/// Records a PulseAudio (or pipewire-pulse) source through `parec`.
+/// `@DEFAULT_MONITOR@` is whatever is playing, `@DEFAULT_SOURCE@` the default microphone.
+pub struct PulseSource {
+    child: Child,
+    stdout: ChildStdout,
+    bytes: Vec<u8>,
+}
+
+impl PulseSource {
+    pub fn new(device: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut child = Command::new("parec")
+            .args([
+                "--device",
+                device,
+                "--format=s16le",
+                &format!("--rate={}", RATE),
+                &format!("--channels={}", CHANNELS),
+                "--latency-msec=20",
+            ])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|e| format!("Could not start parec (install pulseaudio-utils): {}", e))?;
+        let stdout = child.stdout.take().unwrap();
+        Ok(PulseSource { child, stdout, bytes: Vec::new() })
+    }
+}
+
+impl AudioSource for PulseSource {
+    fn sample_rate(&self) -> u32 {
+        RATE
+    }
+
+    fn channels(&self) -> u16 {
+        CHANNELS
+    }
+
+    fn read(&mut self, buf: &mut [i16]) -> io::Result<usize> {
+        self.bytes.resize(buf.len() * 2, 0);
+        let mut n = self.stdout.read(&mut self.bytes)?;
+        if n == 0 {
+            return Ok(0);
+        }
+        // never hand out half a sample
+        if n % 2 == 1 {
+            self.stdout.read_exact(&mut self.bytes[n..n + 1])?;
+            n += 1;
+        }
+        for (s, b) in buf.iter_mut().zip(self.bytes[..n].chunks_exact(2)) {
+            *s = i16::from_le_bytes([b[0], b[1]]);
+        }
+        Ok(n / 2)
+    }
+}
+
+impl Drop for PulseSource {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
@@ -0,0 +1,126 @@
+use super::AudioSource;
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::{SampleFormat, StreamConfig};
+use std::io;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// loopback streams go quiet when nothing is playing, fill that in with silence
+const SILENCE_AFTER: Duration = Duration::from_millis(50);
+
+/// WASAPI capture through cpal. Building an input stream on an output device records what it plays.
+pub struct WasapiSource {
+    sample_rate: u32,
+    channels: u16,
+    rx: Receiver<Vec<i16>>,
+    pending: Vec<i16>,
+    last_data: Instant,
+}
+
+impl WasapiSource {
+    pub fn loopback() -> Result<Self, Box<dyn std::error::Error>> {
+        Self::start(true)
+    }
+
+    pub fn microphone() -> Result<Self, Box<dyn std::error::Error>> {
+        Self::start(false)
+    }
+
+    // cpal streams can't leave the thread that built them, so one is parked per source
+    fn start(loopback: bool) -> Result<Self, Box<dyn std::error::Error>> {
+        let (tx, rx) = mpsc::channel();
+        let (ready_tx, ready_rx) = mpsc::channel();
+
+        thread::spawn(move || {
+            let stream = (|| -> Result<_, Box<dyn std::error::Error>> {
+                let host = cpal::default_host();
+                let (device, config) = if loopback {
+                    let device = host.default_output_device().ok_or("No audio output device")?;
+                    let config = device.default_output_config()?;
+                    (device, config)
+                } else {
+                    let device = host.default_input_device().ok_or("No microphone")?;
+                    let config = device.default_input_config()?;
+                    (device, config)
+                };
+                let format = config.sample_format();
+                let config: StreamConfig = config.into();
+                let err_fn = |e: cpal::StreamError| eprintln!("Audio stream error: {}", e);
+
+                let stream = match format {
+                    SampleFormat::F32 => device.build_input_stream(
+                        &config,
+                        move |data: &[f32], _: &cpal::InputCallbackInfo| {
+                            let _ = tx.send(data.iter().map(|&s| (s.clamp(-1., 1.) * i16::MAX as f32) as i16).collect());
+                        },
+                        err_fn,
+                        None,
+                    )?,
+                    SampleFormat::I16 => device.build_input_stream(
+                        &config,
+                        move |data: &[i16], _: &cpal::InputCallbackInfo| {
+                            let _ = tx.send(data.to_vec());
+                        },
+                        err_fn,
+                        None,
+                    )?,
+                    f => return Err(format!("Unsupported sample format {:?}", f).into()),
+                };
+                stream.play()?;
+                Ok((stream, config.sample_rate.0, config.channels))
+            })();
+
+            match stream {
+                Ok((stream, rate, channels)) => {
+                    let _ = ready_tx.send(Ok((rate, channels)));
+                    let _stream = stream;
+                    loop {
+                        thread::park();
+                    }
+                },
+                Err(e) => {
+                    let _ = ready_tx.send(Err(e.to_string()));
+                },
+            }
+        });
+
+        let (sample_rate, channels) = ready_rx.recv()??;
+        Ok(WasapiSource { sample_rate, channels, rx, pending: Vec::new(), last_data: Instant::now() })
+    }
+}
+
+impl AudioSource for WasapiSource {
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    fn read(&mut self, buf: &mut [i16]) -> io::Result<usize> {
+        if self.pending.is_empty() {
+            match self.rx.recv_timeout(SILENCE_AFTER) {
+                Ok(data) => {
+                    self.pending = data;
+                    self.last_data = Instant::now();
+                },
+                Err(RecvTimeoutError::Timeout) => {
+                    let elapsed = self.last_data.elapsed();
+                    self.last_data = Instant::now();
+                    let frames = (elapsed.as_secs_f64() * self.sample_rate as f64) as usize;
+                    let n = (frames * self.channels as usize).min(buf.len() / self.channels as usize * self.channels as usize);
+                    buf[..n].fill(0);
+                    return Ok(n);
+                },
+                Err(RecvTimeoutError::Disconnected) => return Ok(0),
+            }
+        }
+
+        let n = self.pending.len().min(buf.len());
+        buf[..n].copy_from_slice(&self.pending[..n]);
+        self.pending.drain(..n);
+        Ok(n)
+    }
+}
@@ -0,0 +1,80 @@
+use super::{FrameSource, Monitor, PixelFormat};
+use dxgi_capture_rs::DXGIManager;
+use windows::Win32::Graphics::Dxgi::{CreateDXGIFactory1, IDXGIFactory1};
+
+pub struct DxgiSource {
+    manager: DXGIManager,
+    // the size of the last frame, a display mode change hands over frames of the new one
+    size: (usize, usize),
+}
+
+impl DxgiSource {
+    pub fn new(monitor: usize) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut manager = DXGIManager::new(100)?;
+        if monitor != 0 {
+            manager.set_capture_source_index(monitor);
+        }
+        let size = manager.geometry();
+        Ok(DxgiSource { manager, size })
+    }
+}
+
+// dxgi-capture-rs duplicates the outputs of the first adapter that has any on the desktop and
+// counts the one at 0,0 as source 0, the others follow in the order the adapter lists them.
+// This walks them the same way so the indexes line up.
+pub fn monitors() -> Result<Vec<Monitor>, Box<dyn std::error::Error>> {
+    let factory: IDXGIFactory1 = unsafe { CreateDXGIFactory1()? };
+    let mut outputs = Vec::new();
+    let mut a = 0;
+    while outputs.is_empty() {
+        let Ok(adapter) = (unsafe { factory.EnumAdapters1(a) }) else {
+            break;
+        };
+        a += 1;
+        let mut o = 0;
+        while let Ok(output) = unsafe { adapter.EnumOutputs(o) } {
+            o += 1;
+            let desc = unsafe { output.GetDesc()? };
+            if !desc.AttachedToDesktop.as_bool() {
+                continue;
+            }
+            let end = desc.DeviceName.iter().position(|&c| c == 0).unwrap_or(desc.DeviceName.len());
+            // "\\.\DISPLAY1" is called DISPLAY1, it is the name people type into ack.cfg
+            let name = String::from_utf16_lossy(&desc.DeviceName[..end]);
+            let rect = desc.DesktopCoordinates;
+            outputs.push(Monitor {
+                index: 0,
+                name: name.trim_start_matches(r"\\.\").to_string(),
+                x: rect.left,
+                y: rect.top,
+                width: (rect.right - rect.left) as u32,
+                height: (rect.bottom - rect.top) as u32,
+            });
+        }
+    }
+
+    if let Some(primary) = outputs.iter().position(|m| m.x == 0 && m.y == 0) {
+        let main = outputs.remove(primary);
+        outputs.insert(0, main);
+    }
+    for (index, monitor) in outputs.iter_mut().enumerate() {
+        monitor.index = index;
+    }
+    Ok(outputs)
+}
+
+impl FrameSource for DxgiSource {
+    fn geometry(&self) -> (u32, u32) {
+        (self.size.0 as u32, self.size.1 as u32)
+    }
+
+    fn pixel_format(&self) -> PixelFormat {
+        PixelFormat::Bgra
+    }
+
+    fn capture_frame(&mut self) -> Option<Vec<u8>> {
+        let (data, size) = self.manager.capture_frame_fast().ok()?;
+        self.size = size;
+        Some(data)
+    }
+}
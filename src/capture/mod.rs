@@ -0,0 +1,134 @@
+#[cfg(windows)]
+mod dxgi;
+mod synthetic;
+#[cfg(target_os = "linux")]
+mod x11;
+
+#[cfg(windows)]
+pub use dxgi::DxgiSource;
+pub use synthetic::SyntheticSource;
+#[cfg(target_os = "linux")]
+pub use x11::X11Source;
+
+use std::fmt;
+
+/// One screen a capture source can record, `index` is what `open` takes.
+#[derive(Clone, Debug)]
+pub struct Monitor {
+    pub index: usize,
+    pub name: String,
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl fmt::Display for Monitor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {} {}x{} at {},{}", self.index, self.name, self.width, self.height, self.x, self.y)?;
+        if self.index == 0 {
+            write!(f, " (main)")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PixelFormat {
+    Bgra,
+    /// 4:2:0 with a full size luma plane followed by interleaved chroma, what QSV reads.
+    Nv12,
+    /// 4:2:0 with three planes, ffmpeg's yuv420p.
+    I420,
+}
+
+impl PixelFormat {
+    pub fn ffmpeg_name(self) -> &'static str {
+        match self {
+            PixelFormat::Bgra => "bgra",
+            PixelFormat::Nv12 => "nv12",
+            PixelFormat::I420 => "yuv420p",
+        }
+    }
+
+    /// How the format is named in a NUT stream header.
+    pub fn nut_fourcc(self) -> [u8; 4] {
+        match self {
+            PixelFormat::Bgra => *b"BGRA",
+            PixelFormat::Nv12 => *b"NV12",
+            PixelFormat::I420 => *b"I420",
+        }
+    }
+
+    pub fn frame_size(self, width: u32, height: u32) -> usize {
+        let (width, height) = (width as usize, height as usize);
+        match self {
+            PixelFormat::Bgra => width * height * 4,
+            PixelFormat::Nv12 | PixelFormat::I420 => width * height + 2 * width.div_ceil(2) * height.div_ceil(2),
+        }
+    }
+}
+
+/// A part of the captured picture, in pixels from its top left corner.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Rect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Rect {
+    /// Copies this part out of a BGRA frame `frame_width` pixels wide.
+    pub fn crop(self, frame: &[u8], frame_width: u32) -> Vec<u8> {
+        let (stride, row) = (frame_width as usize * 4, self.width as usize * 4);
+        let mut out = Vec::with_capacity(row * self.height as usize);
+        for line in frame.chunks_exact(stride).skip(self.y as usize).take(self.height as usize) {
+            out.extend_from_slice(&line[self.x as usize * 4..][..row]);
+        }
+        out
+    }
+}
+
+pub trait FrameSource: Send {
+    fn geometry(&self) -> (u32, u32);
+
+    fn pixel_format(&self) -> PixelFormat;
+
+    /// Returns `None` when there is no new frame this tick (timeout, unchanged desktop).
+    fn capture_frame(&mut self) -> Option<Vec<u8>>;
+
+    /// A finite source reports when it has nothing left to give, the recording loop then saves a clip and stops.
+    fn is_finished(&self) -> bool {
+        false
+    }
+}
+
+/// The screens source `name` can record. The main one is always first, so index 0 is what
+/// recording without picking a monitor gets.
+pub fn monitors(name: &str) -> Result<Vec<Monitor>, Box<dyn std::error::Error>> {
+    match name {
+        #[cfg(windows)]
+        "dxgi" => dxgi::monitors(),
+        #[cfg(target_os = "linux")]
+        "x11" => x11::monitors(),
+        "synthetic" => Ok(vec![Monitor { index: 0, name: "synthetic".to_string(), x: 0, y: 0, width: 1280, height: 720 }]),
+        _ => Err(format!("Unknown capture source \"{}\"", name).into()),
+    }
+}
+
+/// `monitor` is an index into what `monitors` returns.
+pub fn open(name: &str, monitor: usize, synthetic_frames: u64) -> Result<Box<dyn FrameSource>, Box<dyn std::error::Error>> {
+    match name {
+        #[cfg(windows)]
+        "dxgi" => Ok(Box::new(DxgiSource::new(monitor)?)),
+        #[cfg(target_os = "linux")]
+        "x11" => Ok(Box::new(X11Source::new(monitor)?)),
+        "synthetic" => Ok(Box::new(SyntheticSource::new(1280, 720, synthetic_frames))),
+        _ => Err(format!("Unknown capture source \"{}\"", name).into()),
+    }
+}
+
+pub fn default_source() -> &'static str {
+    if cfg!(windows) { "dxgi" } else { "x11" }
+}
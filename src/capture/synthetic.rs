@@ -0,0 +1,77 @@
+use super::{FrameSource, PixelFormat};
+
+// SMPTE-ish bars, BGRA
+const BARS: [[u8; 4]; 8] = [
+    [0xC0, 0xC0, 0xC0, 0xFF],
+    [0x00, 0xC0, 0xC0, 0xFF],
+    [0xC0, 0xC0, 0x00, 0xFF],
+    [0x00, 0xC0, 0x00, 0xFF],
+    [0xC0, 0x00, 0xC0, 0xFF],
+    [0x00, 0x00, 0xC0, 0xFF],
+    [0xC0, 0x00, 0x00, 0xFF],
+    [0x10, 0x10, 0x10, 0xFF],
+];
+const COUNTER_BITS: u32 = 32;
+
+/// Deterministic test pattern: color bars scrolling one pixel per frame with the
+/// frame number drawn as a row of black/white bit blocks along the top edge.
+pub struct SyntheticSource {
+    width: u32,
+    height: u32,
+    frame: u64,
+    limit: u64,
+}
+
+impl SyntheticSource {
+    /// `limit` of 0 produces frames forever.
+    pub fn new(width: u32, height: u32, limit: u64) -> Self {
+        SyntheticSource { width, height, frame: 0, limit }
+    }
+
+    pub fn render(&self, frame: u64) -> Vec<u8> {
+        let (width, height) = (self.width as usize, self.height as usize);
+        let bar_width = (width / BARS.len()).max(1);
+        let block = (width / COUNTER_BITS as usize).max(1);
+        let mut data = vec![0u8; width * height * 4];
+
+        for (y, row) in data.chunks_exact_mut(width * 4).enumerate() {
+            for (x, px) in row.chunks_exact_mut(4).enumerate() {
+                let color = if y < block {
+                    let bit = (x / block) as u32;
+                    if bit < COUNTER_BITS && (frame >> (COUNTER_BITS - 1 - bit)) & 1 == 1 {
+                        [0xFF; 4]
+                    } else {
+                        [0x00, 0x00, 0x00, 0xFF]
+                    }
+                } else {
+                    BARS[((x + frame as usize) / bar_width) % BARS.len()]
+                };
+                px.copy_from_slice(&color);
+            }
+        }
+        data
+    }
+}
+
+impl FrameSource for SyntheticSource {
+    fn geometry(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    fn pixel_format(&self) -> PixelFormat {
+        PixelFormat::Bgra
+    }
+
+    fn capture_frame(&mut self) -> Option<Vec<u8>> {
+        if self.is_finished() {
+            return None;
+        }
+        let data = self.render(self.frame);
+        self.frame += 1;
+        Some(data)
+    }
+
+    fn is_finished(&self) -> bool {
+        self.limit != 0 && self.frame >= self.limit
+    }
+}
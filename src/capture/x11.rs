@@ -0,0 +1,177 @@
+use super::{FrameSource, Monitor, PixelFormat};
+use std::ptr;
+use x11rb::connection::Connection;
+use x11rb::protocol::randr::ConnectionExt as _;
+use x11rb::protocol::shm::ConnectionExt as _;
+use x11rb::protocol::xproto::{ConnectionExt as _, ImageFormat, Window};
+use x11rb::rust_connection::RustConnection;
+
+struct ShmSegment {
+    seg: u32,
+    addr: *mut u8,
+    size: usize,
+}
+
+/// Grabs one monitor's part of the root window of the X display in `$DISPLAY` (works the same
+/// against Xvfb). MIT-SHM is used when the server offers it, plain GetImage otherwise.
+pub struct X11Source {
+    conn: RustConnection,
+    root: Window,
+    x: i16,
+    y: i16,
+    width: u16,
+    height: u16,
+    shm: Option<ShmSegment>,
+}
+
+// the shm pointer is only touched from whichever thread owns the source
+unsafe impl Send for X11Source {}
+
+impl X11Source {
+    pub fn new(monitor: usize) -> Result<Self, Box<dyn std::error::Error>> {
+        let (conn, screen_num) = x11rb::connect(None)?;
+        let screen = &conn.setup().roots[screen_num];
+        let (root, depth) = (screen.root, screen.root_depth);
+        let monitors = list_monitors(&conn, screen_num);
+        let Some(m) = monitors.get(monitor) else {
+            return Err(format!("There is no monitor {}, the display has {}", monitor, monitors.len()).into());
+        };
+        let (x, y, width, height) = (m.x as i16, m.y as i16, m.width as u16, m.height as u16);
+
+        let bpp = conn
+            .setup()
+            .pixmap_formats
+            .iter()
+            .find(|f| f.depth == depth)
+            .map(|f| f.bits_per_pixel)
+            .unwrap_or(0);
+        if bpp != 32 {
+            return Err(format!("Unsupported X11 visual: depth {} at {} bits per pixel, 32 bpp is required", depth, bpp).into());
+        }
+
+        let shm = attach_shm(&conn, width as usize * height as usize * 4);
+        Ok(X11Source { conn, root, x, y, width, height, shm })
+    }
+}
+
+pub fn monitors() -> Result<Vec<Monitor>, Box<dyn std::error::Error>> {
+    let (conn, screen_num) = x11rb::connect(None)?;
+    Ok(list_monitors(&conn, screen_num))
+}
+
+// the monitors RandR knows about with the primary one first, or the whole screen as one
+// monitor when the server has no RandR (a bare Xvfb)
+fn list_monitors(conn: &RustConnection, screen_num: usize) -> Vec<Monitor> {
+    let screen = &conn.setup().roots[screen_num];
+    let whole = Monitor {
+        index: 0,
+        name: "screen".to_string(),
+        x: 0,
+        y: 0,
+        width: screen.width_in_pixels as u32,
+        height: screen.height_in_pixels as u32,
+    };
+
+    let reply = conn.randr_get_monitors(screen.root, true).ok().and_then(|c| c.reply().ok());
+    let mut found: Vec<(bool, Monitor)> = Vec::new();
+    for info in reply.map(|r| r.monitors).unwrap_or_default() {
+        let name = conn
+            .get_atom_name(info.name)
+            .ok()
+            .and_then(|c| c.reply().ok())
+            .map(|r| String::from_utf8_lossy(&r.name).into_owned())
+            .unwrap_or_default();
+        let monitor = Monitor {
+            index: 0,
+            name,
+            x: info.x as i32,
+            y: info.y as i32,
+            width: info.width as u32,
+            height: info.height as u32,
+        };
+        found.push((info.primary, monitor));
+    }
+    if found.is_empty() {
+        return vec![whole];
+    }
+    // stable, so the rest keep the order RandR gave them
+    found.sort_by_key(|(primary, _)| !primary);
+    found
+        .into_iter()
+        .enumerate()
+        .map(|(index, (_, monitor))| Monitor { index, ..monitor })
+        .collect()
+}
+
+fn attach_shm(conn: &RustConnection, size: usize) -> Option<ShmSegment> {
+    conn.shm_query_version().ok()?.reply().ok()?;
+
+    unsafe {
+        let id = libc::shmget(libc::IPC_PRIVATE, size, libc::IPC_CREAT | 0o600);
+        if id < 0 {
+            return None;
+        }
+        let addr = libc::shmat(id, ptr::null(), 0);
+        // marked for removal right away, it goes away once both sides detach
+        libc::shmctl(id, libc::IPC_RMID, ptr::null_mut());
+        if addr as isize == -1 {
+            return None;
+        }
+
+        let attached = conn
+            .generate_id()
+            .ok()
+            .and_then(|seg| conn.shm_attach(seg, id as u32, false).ok()?.check().ok().map(|_| seg));
+        match attached {
+            Some(seg) => Some(ShmSegment { seg, addr: addr as *mut u8, size }),
+            None => {
+                libc::shmdt(addr);
+                None
+            },
+        }
+    }
+}
+
+impl FrameSource for X11Source {
+    fn geometry(&self) -> (u32, u32) {
+        (self.width as u32, self.height as u32)
+    }
+
+    fn pixel_format(&self) -> PixelFormat {
+        PixelFormat::Bgra
+    }
+
+    fn capture_frame(&mut self) -> Option<Vec<u8>> {
+        match &self.shm {
+            Some(shm) => {
+                self.conn
+                    .shm_get_image(self.root, self.x, self.y, self.width, self.height, !0, ImageFormat::Z_PIXMAP.into(), shm.seg, 0)
+                    .ok()?
+                    .reply()
+                    .ok()?;
+                Some(unsafe { std::slice::from_raw_parts(shm.addr, shm.size) }.to_vec())
+            },
+            None => {
+                let reply = self
+                    .conn
+                    .get_image(ImageFormat::Z_PIXMAP, self.root, self.x, self.y, self.width, self.height, !0)
+                    .ok()?
+                    .reply()
+                    .ok()?;
+                Some(reply.data)
+            },
+        }
+    }
+}
+
+impl Drop for X11Source {
+    fn drop(&mut self) {
+        if let Some(shm) = self.shm.take() {
+            let _ = self.conn.shm_detach(shm.seg);
+            let _ = self.conn.flush();
+            unsafe {
+                libc::shmdt(shm.addr as *const libc::c_void);
+            }
+        }
+    }
+}
@@ -0,0 +1,115 @@
+use crate::diagnostics;
+use crate::encoder::Codec;
+use crate::platform;
+use crate::replay::Snapshot;
+use std::process::Stdio;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use time::{OffsetDateTime, format_description};
+
+pub struct Clip {
+    pub path: String,
+    pub duration: Duration,
+}
+
+/// Writes clips on background threads so capture never waits on ffmpeg.
+/// Dropping it waits for the clips that are still being written.
+pub struct ClipSaver {
+    container: &'static str,
+    codec: Codec,
+    label: Option<String>,
+    jobs: Vec<JoinHandle<()>>,
+}
+
+impl ClipSaver {
+    /// `container` is the clip file extension, `mp4` or `mkv`. A `label` goes at the end of every
+    /// file name, to tell apart the clips of monitors saved at the same moment.
+    pub fn new(container: &'static str, label: Option<String>) -> Self {
+        ClipSaver { container, codec: Codec::H264, label, jobs: Vec::new() }
+    }
+
+    pub fn set_container(&mut self, container: &'static str) {
+        self.container = container;
+    }
+
+    /// The codec of what's in the buffer, some need a different tag in mp4.
+    pub fn set_codec(&mut self, codec: Codec) {
+        self.codec = codec;
+    }
+
+    /// `end` is the buffer time (ms) the clip stops at.
+    pub fn save(&mut self, snapshot: Snapshot, end: u64) {
+        self.jobs.retain(|job| !job.is_finished());
+        let container = self.container;
+        let tag = if container == "mp4" { self.codec.mp4_tag() } else { None };
+        let label = self.label.clone();
+        self.jobs.push(thread::spawn(move || match save_final_clip(snapshot, end, container, tag, label.as_deref()) {
+            Ok(clip) => eprintln!("Saved {} ({:.2}s)", clip.path, clip.duration.as_secs_f64()),
+            Err(e) => platform::show_error("Clip Error", &e.to_string()),
+        }));
+    }
+}
+
+impl Drop for ClipSaver {
+    fn drop(&mut self) {
+        for job in self.jobs.drain(..) {
+            let _ = job.join();
+        }
+    }
+}
+
+/// The snapshot starts on a keyframe since nothing is re-encoded, so the clip can be up to a GOP
+/// longer than asked for. The tail past `end` is cut off.
+fn save_final_clip(
+    snapshot: Snapshot,
+    end: u64,
+    container: &str,
+    tag: Option<&str>,
+    label: Option<&str>,
+) -> Result<Clip, Box<dyn std::error::Error>> {
+    platform::beep(1000);
+
+    // a press saved before the encoder caught up to it only has what got there
+    let duration = Duration::from_millis(end.min(snapshot.end).saturating_sub(snapshot.start));
+
+    let now = OffsetDateTime::now_utc();
+    let fmt = format_description::parse("[year]-[month]-[day].[hour]_[minute]_[second].[subsecond digits:3]")?;
+    let output_name = match label {
+        Some(label) => format!("clip_{}_{}.{}", now.format(&fmt)?, label, container),
+        None => format!("clip_{}.{}", now.format(&fmt)?, container),
+    };
+
+    let mut args: Vec<String> = diagnostics::FFMPEG_ARGS.map(String::from).to_vec();
+    args.extend(["-y", "-f", "matroska", "-i", "-", "-map", "0", "-c", "copy"].map(String::from));
+    if let Some(tag) = tag {
+        args.extend(["-tag:v".to_string(), tag.to_string()]);
+    }
+    args.extend(["-t".to_string(), format!("{:.3}", duration.as_secs_f64()), output_name.clone()]);
+
+    let mut child = platform::ffmpeg()
+        .args(&args)
+        .stdin(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    let mut diagnostics = diagnostics::watch(child.stderr.take().unwrap(), &output_name);
+    let mut stdin = child.stdin.take().unwrap();
+    // a write only fails when ffmpeg gave up, and then its own reason is the one worth showing
+    let written = snapshot.write_to(&mut stdin);
+    drop(stdin);
+    platform::beep(2000);
+    let status = child.wait()?;
+    let error = diagnostics.finish();
+
+    if !status.success() || written.is_err() {
+        let reason = match (error, written) {
+            (Some(error), _) => error,
+            (None, Err(e)) => e.to_string(),
+            (None, Ok(())) => status.to_string(),
+        };
+        return Err(format!("ffmpeg could not write {}: {}\n\nMore in {}.", output_name, reason, diagnostics::LOG_PATH).into());
+    }
+
+    platform::beep(3000);
+    Ok(Clip { path: output_name, duration })
+}
@@ -0,0 +1,768 @@
+use crate::capture::{self, Monitor, Rect};
+use crate::encoder::{self, EncoderSettings, RateControl};
+use crate::hotkey::Chord;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+use std::time::{Duration, Instant, SystemTime};
+use tinyjson::JsonValue;
+
+pub const CONFIG_PATH: &str = "ack.cfg";
+const BACKUP_PATH: &str = "ack.cfg.bak";
+
+const DEFAULT_CFG: &str = "{\"time\":10,\"fps\":60,\"kbps\":10000,\"key\":\"F10\", \"encoder\": \"libx264\"}";
+
+const ENCODER_KEYS: &[&str] = &["preset", "rate_control", "quality", "gop", "profile"];
+
+const WATCH_INTERVAL: Duration = Duration::from_millis(500);
+
+const KNOWN_KEYS: &[&str] = &[
+    "time",
+    "fps",
+    "kbps",
+    "key",
+    "encoder",
+    "encoders",
+    "encoder_fallbacks",
+    "saves",
+    "quit_key",
+    "pause_key",
+    "capture",
+    "monitor",
+    "crop",
+    "resolution",
+    "synthetic_frames",
+    "ffmpeg",
+    "audio",
+    "audio_mode",
+    "container",
+];
+
+#[derive(Clone)]
+pub struct Config {
+    pub saves: Vec<SaveConfig>,
+    pub quit_key: Chord,
+    pub pause_key: Option<Chord>,
+    pub fps: i32,
+    pub kbps: i32,
+    pub time: i32,
+    pub encoder: EncoderSettings,
+    /// Tried in order when `encoder` doesn't work, always ends with libx264.
+    pub encoder_fallbacks: Vec<EncoderSettings>,
+    pub capture: String,
+    /// Each one gets its own recording, at least one is always there.
+    pub monitors: Vec<MonitorChoice>,
+    pub crop: Crop,
+    pub resolution: Resolution,
+    pub synthetic_frames: u64,
+    /// Path to ffmpeg, empty to look next to moment and on PATH.
+    pub ffmpeg: String,
+    pub audio: Vec<AudioConfig>,
+    pub audio_mode: AudioMode,
+    pub container: String,
+}
+
+#[derive(Clone)]
+pub struct SaveConfig {
+    pub key: Chord,
+    pub time: i32,
+}
+
+#[derive(Clone, PartialEq)]
+pub struct AudioConfig {
+    pub source: String,
+    pub name: String,
+    pub volume: f32,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum MonitorChoice {
+    /// As counted by `capture::monitors`, 0 is the main one.
+    Index(usize),
+    Name(String),
+    All,
+}
+
+impl MonitorChoice {
+    /// Indexes into `monitors`.
+    pub fn resolve(&self, monitors: &[Monitor]) -> Result<Vec<usize>, String> {
+        let (found, what) = match self {
+            MonitorChoice::All => return Ok((0..monitors.len()).collect()),
+            MonitorChoice::Index(index) => (monitors.iter().find(|m| m.index == *index), index.to_string()),
+            MonitorChoice::Name(name) => (monitors.iter().find(|m| m.name.eq_ignore_ascii_case(name)), format!("\"{}\"", name)),
+        };
+        found.map(|m| vec![m.index]).ok_or_else(|| {
+            let list = monitors.iter().map(|m| m.to_string()).collect::<Vec<_>>().join("\n");
+            format!("{} is not one of the monitors:\n{}", what, list)
+        })
+    }
+}
+
+const CROP_PRESETS: &[&str] = &["left", "right", "top", "bottom", "center", "16:9"];
+
+/// The part of the screen that is recorded. Only checked against the screen once it is known.
+#[derive(Clone, PartialEq)]
+pub enum Crop {
+    Full,
+    Area(Rect),
+    /// One of `CROP_PRESETS`.
+    Preset(String),
+}
+
+impl Crop {
+    /// None for the whole picture. Sizes are rounded down to even, 4:2:0 video needs that.
+    pub fn resolve(&self, width: u32, height: u32) -> Result<Option<Rect>, String> {
+        let rect = |x, y, w, h| Rect { x, y, width: w, height: h };
+        let area = match self {
+            Crop::Full => return Ok(None),
+            Crop::Area(area) => *area,
+            Crop::Preset(preset) => match preset.as_str() {
+                "left" => rect(0, 0, width / 2, height),
+                "right" => rect(width - width / 2, 0, width / 2, height),
+                "top" => rect(0, 0, width, height / 2),
+                "bottom" => rect(0, height - height / 2, width, height / 2),
+                "center" => rect(width / 4, height / 4, width / 2, height / 2),
+                // the widest 16:9 part in the middle, for ultrawide screens
+                _ => {
+                    let w = width.min(height * 16 / 9);
+                    let h = height.min(width * 9 / 16);
+                    rect((width - w) / 2, (height - h) / 2, w, h)
+                },
+            },
+        };
+
+        if area.x + area.width > width || area.y + area.height > height {
+            return Err(format!(
+                "x {} y {} width {} height {} goes past the edge of the {}x{} screen",
+                area.x, area.y, area.width, area.height, width, height
+            ));
+        }
+        let area = Rect { width: area.width & !1, height: area.height & !1, ..area };
+        if area.width < 2 || area.height < 2 {
+            return Err(format!("{}x{} is too small to record", area.width, area.height));
+        }
+        Ok(Some(area))
+    }
+}
+
+/// The size frames are recorded at. Only ever shrinks, a larger size than the capture records
+/// at the capture size.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Resolution {
+    Source,
+    /// A fraction of the captured size.
+    Scale(f64),
+    /// Fits inside this size, keeping the shape of the picture.
+    Fit(u32, u32),
+}
+
+impl Resolution {
+    /// Scaled sizes come out even, 4:2:0 video needs that.
+    pub fn resolve(self, width: u32, height: u32) -> (u32, u32) {
+        let (factor, most) = match self {
+            Resolution::Source => return (width, height),
+            Resolution::Scale(factor) => (factor, (width, height)),
+            Resolution::Fit(w, h) => ((w as f64 / width as f64).min(h as f64 / height as f64), (w, h)),
+        };
+        if factor >= 1. {
+            return (width, height);
+        }
+        let even = |n: u32, most: u32| (((n as f64 * factor).round() as u32).min(most) & !1).max(2).min(n);
+        (even(width, most.0), even(height, most.1))
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum AudioMode {
+    Mixed,
+    Multitrack,
+}
+
+/// Reads `ack.cfg`, creating it if it doesn't exist. Keys that are missing or wrong fall back
+/// to their defaults and come back as problems, the file itself is left alone. Only a file that
+/// isn't JSON at all gets replaced, and a copy is kept in `ack.cfg.bak` first. One that can't be
+/// read, or isn't UTF-8, is never touched.
+pub fn load() -> (Config, Vec<String>) {
+    let text = match fs::read_to_string(CONFIG_PATH) {
+        Ok(text) => text,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            let _ = fs::write(CONFIG_PATH, DEFAULT_CFG);
+            String::from(DEFAULT_CFG)
+        },
+        Err(e) => {
+            let problem = format!("Could not read {} ({}), it was left as is. It has to be saved as UTF-8.", CONFIG_PATH, e);
+            return (parse(DEFAULT_CFG).0, vec![problem]);
+        },
+    };
+
+    let (config, mut problems) = parse(&text);
+    if text.parse::<JsonValue>().map(|v| v.is_object()).unwrap_or(false) {
+        return (config, problems);
+    }
+
+    match fs::copy(CONFIG_PATH, BACKUP_PATH) {
+        Ok(_) => {
+            let _ = fs::write(CONFIG_PATH, DEFAULT_CFG);
+            problems.push(format!("The old file was saved as {} and {} was reset to defaults.", BACKUP_PATH, CONFIG_PATH));
+        },
+        Err(e) => problems.push(format!("Could not back up {} ({}), it was left as is.", CONFIG_PATH, e)),
+    }
+    (config, problems)
+}
+
+/// Every problem found is reported, each with the line of the key it is about.
+pub fn parse(text: &str) -> (Config, Vec<String>) {
+    let parsed = match text.parse::<JsonValue>() {
+        Ok(parsed) => parsed,
+        Err(e) => return (parse(DEFAULT_CFG).0, vec![e.to_string()]),
+    };
+    let empty = HashMap::new();
+    let mut r = Reader { text, problems: Vec::new() };
+    let map: &HashMap<String, JsonValue> = match parsed.get() {
+        Some(map) => map,
+        None => {
+            r.problems.push("The file must be a JSON object ({ ... })".to_string());
+            &empty
+        },
+    };
+
+    for key in map.keys() {
+        if !KNOWN_KEYS.contains(&key.as_str()) {
+            r.problem(key, "is not a known setting".to_string());
+        }
+    }
+
+    let time = r.whole(map, "time", 10., 1. ..=3600., "a number of seconds from 1 to 3600");
+    let fps = r.whole(map, "fps", 60., 1. ..=240., "a frame rate from 1 to 240");
+    let kbps = r.whole(map, "kbps", 10000., 100. ..=500_000., "a bitrate from 100 to 500000 kbps");
+    let (encoder, encoder_fallbacks) = r.encoders(map, "libx264");
+    let quit_key = r.chord(map, "quit_key", "LControl+F1");
+    let pause_key = match map.get("pause_key") {
+        Some(_) => Some(r.chord(map, "pause_key", "")).filter(|c| *c != Chord::default()),
+        None => None,
+    };
+    let capture = r.string(map, "capture", capture::default_source());
+    let monitors = r.monitors(map);
+    let crop = r.crop(map);
+    let resolution = r.resolution(map);
+    let synthetic_frames = r.whole(map, "synthetic_frames", 0., 0. ..=u32::MAX as f64, "a frame count, 0 for no limit");
+    let ffmpeg = r.string(map, "ffmpeg", "");
+
+    let mut audio = Vec::new();
+    for (i, entry) in r.list(map, "audio").into_iter().enumerate() {
+        let path = format!("audio[{}]", i);
+        let source = match entry.get("source").and_then(|v| v.get::<String>()) {
+            Some(source) => source.clone(),
+            None => {
+                r.problem_at("audio", &path, "needs a \"source\" string".to_string());
+                continue;
+            },
+        };
+        let volume = r.num(entry, "volume", 1., |v| (0. ..=10.).contains(&v), "a volume from 0 to 10");
+        let name = r.string(entry, "name", &source);
+        audio.push(AudioConfig { source, name, volume: volume as f32 });
+    }
+
+    let audio_mode = match r.string(map, "audio_mode", "mixed").as_str() {
+        "multitrack" => AudioMode::Multitrack,
+        "mixed" => AudioMode::Mixed,
+        other => {
+            r.problem("audio_mode", format!("must be \"mixed\" or \"multitrack\", not \"{}\"", other));
+            AudioMode::Mixed
+        },
+    };
+
+    // a list of {"key", "time"} saves, or the single "key" saving the whole buffer
+    let mut saves = Vec::new();
+    if map.contains_key("saves") {
+        for (i, entry) in r.list(map, "saves").into_iter().enumerate() {
+            let path = format!("saves[{}]", i);
+            let key = match entry.get("key").and_then(|v| v.get::<String>()) {
+                Some(key) => key,
+                None => {
+                    r.problem_at("saves", &path, "needs a \"key\" string".to_string());
+                    continue;
+                },
+            };
+            let key = match Chord::from_str(key) {
+                Ok(key) => key,
+                Err(e) => {
+                    r.problem_at("saves", &path, e);
+                    continue;
+                },
+            };
+            let save_time = r.whole(entry, "time", time, 1. ..=time, &format!("a number of seconds from 1 to time ({})", time));
+            saves.push(SaveConfig { key, time: save_time as i32 });
+        }
+        if saves.is_empty() {
+            r.problem("saves", "has no save that can be used, \"key\" is used instead".to_string());
+        }
+    }
+    if saves.is_empty() {
+        saves.push(SaveConfig { key: r.chord(map, "key", "F10"), time: time as i32 });
+    }
+
+    let container = r.string(map, "container", "mp4");
+    let container = if container == "mp4" || container == "mkv" {
+        container
+    } else {
+        r.problem("container", format!("must be \"mp4\" or \"mkv\", not \"{}\"", container));
+        "mp4".to_string()
+    };
+
+    let config = Config {
+        saves,
+        quit_key,
+        pause_key,
+        fps: fps as i32,
+        kbps: kbps as i32,
+        time: time as i32,
+        encoder,
+        encoder_fallbacks,
+        capture,
+        monitors,
+        crop,
+        resolution,
+        synthetic_frames: synthetic_frames as u64,
+        ffmpeg,
+        audio,
+        audio_mode,
+        container,
+    };
+    (config, r.problems)
+}
+
+/// Notices edits to `ack.cfg` by polling its modification time.
+pub struct Watcher {
+    modified: Option<SystemTime>,
+    settling: Option<SystemTime>,
+    last_check: Instant,
+}
+
+impl Watcher {
+    pub fn new() -> Self {
+        Watcher { modified: modified(), settling: None, last_check: Instant::now() }
+    }
+
+    /// The new contents once the file changed and then sat still for one check,
+    /// so an editor that truncates before writing isn't caught halfway.
+    pub fn poll(&mut self) -> Option<String> {
+        if self.last_check.elapsed() < WATCH_INTERVAL {
+            return None;
+        }
+        self.last_check = Instant::now();
+
+        let now = modified();
+        if now == self.modified {
+            self.settling = None;
+            return None;
+        }
+        if self.settling != now {
+            self.settling = now;
+            return None;
+        }
+        self.modified = now;
+        self.settling = None;
+        fs::read_to_string(CONFIG_PATH).ok()
+    }
+}
+
+fn modified() -> Option<SystemTime> {
+    fs::metadata(CONFIG_PATH).and_then(|m| m.modified()).ok()
+}
+
+struct Reader<'a> {
+    text: &'a str,
+    problems: Vec<String>,
+}
+
+impl Reader<'_> {
+    // tinyjson keeps no positions, the first mention of the key is close enough
+    fn line_of(&self, key: &str) -> Option<usize> {
+        let at = self.text.find(&format!("\"{}\"", key))?;
+        Some(self.text[..at].matches('\n').count() + 1)
+    }
+
+    fn problem(&mut self, key: &str, msg: String) {
+        self.problem_at(key, key, msg);
+    }
+
+    // `key` is looked up for the line number, `path` is what the user reads
+    fn problem_at(&mut self, key: &str, path: &str, msg: String) {
+        let problem = match self.line_of(key) {
+            Some(line) => format!("line {}: \"{}\" {}", line, path, msg),
+            None => format!("\"{}\" {}", path, msg),
+        };
+        self.problems.push(problem);
+    }
+
+    fn num(&mut self, map: &HashMap<String, JsonValue>, key: &str, default: f64, valid: impl Fn(f64) -> bool, expected: &str) -> f64 {
+        match map.get(key) {
+            None => default,
+            Some(v) => match v.get::<f64>() {
+                Some(&n) if valid(n) => n,
+                _ => {
+                    self.problem(key, format!("must be {}, not {}", expected, describe(v)));
+                    default
+                },
+            },
+        }
+    }
+
+    // counts and durations end up as integers, a fraction would round down to something else
+    fn whole(&mut self, map: &HashMap<String, JsonValue>, key: &str, default: f64, range: RangeInclusive<f64>, expected: &str) -> f64 {
+        self.num(map, key, default, |v| v.fract() == 0. && range.contains(&v), expected)
+    }
+
+    fn string(&mut self, map: &HashMap<String, JsonValue>, key: &str, default: &str) -> String {
+        match map.get(key) {
+            None => default.to_string(),
+            Some(v) => match v.get::<String>() {
+                Some(s) => s.clone(),
+                None => {
+                    self.problem(key, format!("must be a string, not {}", describe(v)));
+                    default.to_string()
+                },
+            },
+        }
+    }
+
+    fn chord(&mut self, map: &HashMap<String, JsonValue>, key: &str, default: &str) -> Chord {
+        let fallback = || Chord::from_str(default).unwrap_or_default();
+        let value = match map.get(key) {
+            None => return fallback(),
+            Some(v) => v,
+        };
+        match value.get::<String>().map(|s| Chord::from_str(s)) {
+            Some(Ok(chord)) => chord,
+            Some(Err(e)) => {
+                self.problem(key, e);
+                fallback()
+            },
+            None => {
+                self.problem(key, format!("must be a key chord like \"LControl+F10\", not {}", describe(value)));
+                fallback()
+            },
+        }
+    }
+
+    // an index, a name, "all" or a list of indexes and names
+    fn monitors(&mut self, map: &HashMap<String, JsonValue>) -> Vec<MonitorChoice> {
+        let expected = "a monitor number, a monitor name, \"all\" or a list of numbers and names";
+        let choice = |v: &JsonValue| match (v.get::<f64>(), v.get::<String>()) {
+            (Some(&n), _) if n >= 0. && n.fract() == 0. => Some(MonitorChoice::Index(n as usize)),
+            (_, Some(name)) if name == "all" => Some(MonitorChoice::All),
+            (_, Some(name)) if !name.is_empty() => Some(MonitorChoice::Name(name.clone())),
+            _ => None,
+        };
+
+        let mut choices = Vec::new();
+        match map.get("monitor") {
+            None => {},
+            Some(v) => match v.get::<Vec<JsonValue>>() {
+                Some(list) => {
+                    for (i, entry) in list.iter().enumerate() {
+                        match choice(entry) {
+                            Some(c) => choices.push(c),
+                            None => self.problem_at("monitor", &format!("monitor[{}]", i), format!("must be {}, not {}", expected, describe(entry))),
+                        }
+                    }
+                },
+                None => match choice(v) {
+                    Some(c) => choices.push(c),
+                    None => self.problem("monitor", format!("must be {}, not {}", expected, describe(v))),
+                },
+            },
+        }
+        if choices.is_empty() {
+            choices.push(MonitorChoice::Index(0));
+        }
+        choices
+    }
+
+    // {"x", "y", "width", "height"} in pixels, or the name of a preset
+    fn crop(&mut self, map: &HashMap<String, JsonValue>) -> Crop {
+        let value = match map.get("crop") {
+            None => return Crop::Full,
+            Some(v) => v,
+        };
+        if let Some(preset) = value.get::<String>() {
+            if CROP_PRESETS.contains(&preset.as_str()) {
+                return Crop::Preset(preset.clone());
+            }
+        } else if let Some(area) = value.get::<HashMap<String, JsonValue>>() {
+            for key in area.keys() {
+                if !["x", "y", "width", "height"].contains(&key.as_str()) {
+                    self.problem_at(key, &format!("crop.{}", key), "is not a known crop option".to_string());
+                }
+            }
+            let mut side = |key: &str, min: f64| {
+                let expected = format!("a whole number of pixels from {} to 16384", min);
+                self.num(area, key, min, |v| v.fract() == 0. && (min..=16384.).contains(&v), &expected) as u32
+            };
+            let (x, y) = (side("x", 0.), side("y", 0.));
+            let (width, height) = (side("width", 2.), side("height", 2.));
+            if !area.contains_key("width") || !area.contains_key("height") {
+                self.problem("crop", "needs a \"width\" and a \"height\"".to_string());
+                return Crop::Full;
+            }
+            return Crop::Area(Rect { x, y, width, height });
+        }
+        let presets = CROP_PRESETS.iter().map(|p| format!("\"{}\"", p)).collect::<Vec<_>>().join(", ");
+        self.problem("crop", format!("must be {{\"x\", \"y\", \"width\", \"height\"}} or one of {}, not {}", presets, describe(value)));
+        Crop::Full
+    }
+
+    // "1920x1080", "source" or a scale factor like 0.5
+    fn resolution(&mut self, map: &HashMap<String, JsonValue>) -> Resolution {
+        let expected = "a size like \"1920x1080\", \"source\" or a scale from 0.1 to 1";
+        let value = match map.get("resolution") {
+            None => return Resolution::Source,
+            Some(v) => v,
+        };
+        if let Some(&factor) = value.get::<f64>() {
+            if (0.1..=1.).contains(&factor) {
+                return Resolution::Scale(factor);
+            }
+        } else if let Some(size) = value.get::<String>() {
+            if size == "source" {
+                return Resolution::Source;
+            }
+            let parsed = size.split_once('x').and_then(|(w, h)| Some((w.trim().parse::<u32>().ok()?, h.trim().parse::<u32>().ok()?)));
+            if let Some((w, h)) = parsed
+                && (16..=16384).contains(&w)
+                && (16..=16384).contains(&h)
+            {
+                return Resolution::Fit(w, h);
+            }
+        }
+        self.problem("resolution", format!("must be {}, not {}", expected, describe(value)));
+        Resolution::Source
+    }
+
+    // a name like "h264_nvenc" (0-3 still mean what they used to) with its entry from "encoders",
+    // then the same for each name in "encoder_fallbacks"
+    fn encoders(&mut self, map: &HashMap<String, JsonValue>, default: &str) -> (EncoderSettings, Vec<EncoderSettings>) {
+        let tables = self.encoder_tables(map);
+        let settings = |name: &str| tables.get(name).cloned().unwrap_or_else(|| EncoderSettings::new(name));
+
+        let name = match map.get("encoder") {
+            None => default.to_string(),
+            Some(v) => match (v.get::<String>(), v.get::<f64>()) {
+                (Some(name), _) if encoder::backend(name).is_some() => name.clone(),
+                (_, Some(&n)) if [0., 1., 2., 3.].contains(&n) => encoder::LEGACY_ENCODERS[n as usize].to_string(),
+                _ => {
+                    self.problem("encoder", format!("must be one of {}, not {}", encoder_names(), describe(v)));
+                    default.to_string()
+                },
+            },
+        };
+
+        let mut fallbacks = Vec::new();
+        let empty = Vec::new();
+        let list = match map.get("encoder_fallbacks") {
+            None => &empty,
+            Some(v) => match v.get::<Vec<JsonValue>>() {
+                Some(list) => list,
+                None => {
+                    self.problem("encoder_fallbacks", format!("must be a list of encoder names, not {}", describe(v)));
+                    &empty
+                },
+            },
+        };
+        for (i, entry) in list.iter().enumerate() {
+            match entry.get::<String>() {
+                Some(fallback) if encoder::backend(fallback).is_some() => fallbacks.push(settings(fallback)),
+                _ => self.problem_at(
+                    "encoder_fallbacks",
+                    &format!("encoder_fallbacks[{}]", i),
+                    format!("must be one of {}, not {}", encoder_names(), describe(entry)),
+                ),
+            }
+        }
+        if !fallbacks.iter().any(|f| f.name == "libx264") {
+            fallbacks.push(settings("libx264"));
+        }
+        (settings(&name), fallbacks)
+    }
+
+    // every table is checked, not just the one in use, so switching encoders doesn't turn up old mistakes
+    fn encoder_tables(&mut self, map: &HashMap<String, JsonValue>) -> HashMap<String, EncoderSettings> {
+        let mut parsed = HashMap::new();
+        let tables = match map.get("encoders") {
+            None => return parsed,
+            Some(v) => match v.get::<HashMap<String, JsonValue>>() {
+                Some(tables) => tables,
+                None => {
+                    self.problem("encoders", format!("must be an object of encoder names, not {}", describe(v)));
+                    return parsed;
+                },
+            },
+        };
+
+        for (name, table) in tables {
+            let path = format!("encoders.{}", name);
+            if encoder::backend(name).is_none() {
+                self.problem_at(name, &path, format!("is not an encoder, use one of {}", encoder_names()));
+                continue;
+            }
+            let Some(table) = table.get::<HashMap<String, JsonValue>>() else {
+                self.problem_at(name, &path, format!("must be an object, not {}", describe(table)));
+                continue;
+            };
+            let settings = self.encoder_table(name, table);
+            parsed.insert(name.clone(), settings);
+        }
+        parsed
+    }
+
+    fn encoder_table(&mut self, name: &str, table: &HashMap<String, JsonValue>) -> EncoderSettings {
+        for key in table.keys() {
+            if !ENCODER_KEYS.contains(&key.as_str()) {
+                self.problem_at(key, &format!("encoders.{}.{}", name, key), "is not a known encoder option".to_string());
+            }
+        }
+
+        let mut settings = EncoderSettings::new(name);
+        if table.contains_key("preset") {
+            settings.preset = Some(self.string(table, "preset", ""));
+        }
+        if table.contains_key("profile") {
+            settings.profile = Some(self.string(table, "profile", ""));
+        }
+        let rate_control = self.string(table, "rate_control", "vbr");
+        settings.rate_control = match RateControl::from_str(&rate_control) {
+            Ok(rate_control) => rate_control,
+            Err(e) => {
+                self.problem_at("rate_control", &format!("encoders.{}.rate_control", name), e);
+                RateControl::Vbr
+            },
+        };
+        let max = settings.codec().max_quality() as f64;
+        settings.quality = self.whole(table, "quality", 23., 0. ..=max, &format!("a quality value from 0 to {}", max)) as u32;
+        if table.contains_key("gop") {
+            settings.gop = Some(self.whole(table, "gop", 60., 1. ..=1000., "a keyframe interval from 1 to 1000 frames") as u32);
+        }
+        settings
+    }
+
+    fn list<'m>(&mut self, map: &'m HashMap<String, JsonValue>, key: &str) -> Vec<&'m HashMap<String, JsonValue>> {
+        let list = match map.get(key) {
+            None => return Vec::new(),
+            Some(v) => match v.get::<Vec<JsonValue>>() {
+                Some(list) => list,
+                None => {
+                    self.problem(key, format!("must be a list, not {}", describe(v)));
+                    return Vec::new();
+                },
+            },
+        };
+
+        let mut entries = Vec::new();
+        for (i, entry) in list.iter().enumerate() {
+            match entry.get::<HashMap<String, JsonValue>>() {
+                Some(entry) => entries.push(entry),
+                None => self.problem_at(key, &format!("{}[{}]", key, i), format!("must be an object, not {}", describe(entry))),
+            }
+        }
+        entries
+    }
+}
+
+fn encoder_names() -> String {
+    encoder::ENCODERS.iter().map(|(n, _, _)| format!("\"{}\"", n)).collect::<Vec<_>>().join(", ")
+}
+
+fn describe(v: &JsonValue) -> String {
+    match v {
+        JsonValue::Number(n) => n.to_string(),
+        JsonValue::String(s) => format!("\"{}\"", s),
+        JsonValue::Boolean(b) => b.to_string(),
+        JsonValue::Null => "null".to_string(),
+        JsonValue::Array(_) => "a list".to_string(),
+        JsonValue::Object(_) => "an object".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(chord: &str) -> Chord {
+        Chord::from_str(chord).unwrap()
+    }
+
+    #[test]
+    fn empty_object_is_all_defaults() {
+        let (config, problems) = parse("{}");
+        assert!(problems.is_empty(), "{:?}", problems);
+        assert_eq!((config.time, config.fps, config.kbps), (10, 60, 10000));
+        assert_eq!(config.saves.len(), 1);
+        assert!(config.saves[0].key == key("F10") && config.saves[0].time == 10);
+        assert!(config.quit_key == key("LControl+F1"));
+        assert!(config.pause_key.is_none());
+        assert_eq!(config.monitors, [MonitorChoice::Index(0)]);
+    }
+
+    #[test]
+    fn default_file_has_no_problems() {
+        assert!(parse(DEFAULT_CFG).1.is_empty());
+    }
+
+    #[test]
+    fn fractions_fall_back_to_defaults() {
+        let (config, problems) = parse(r#"{"fps": 0.5, "time": 0.5, "kbps": 1000.5, "synthetic_frames": 1.5}"#);
+        assert_eq!((config.time, config.fps, config.kbps, config.synthetic_frames), (10, 60, 10000, 0));
+        assert_eq!(problems.len(), 4, "{:?}", problems);
+    }
+
+    #[test]
+    fn out_of_range_is_reported_with_its_line() {
+        let (config, problems) = parse("{\n  \"time\": 30,\n  \"fps\": 500\n}");
+        assert_eq!((config.time, config.fps), (30, 60));
+        assert_eq!(problems, ["line 3: \"fps\" must be a frame rate from 1 to 240, not 500"]);
+    }
+
+    #[test]
+    fn unknown_keys_are_reported() {
+        let (_, problems) = parse(r#"{"fsp": 30}"#);
+        assert_eq!(problems, ["line 1: \"fsp\" is not a known setting"]);
+    }
+
+    #[test]
+    fn not_json_gives_the_defaults() {
+        let (config, problems) = parse("{\"fps\": 30");
+        assert_eq!(config.fps, 60);
+        assert_eq!(problems.len(), 1);
+    }
+
+    #[test]
+    fn saves_are_checked_against_time() {
+        let (config, problems) = parse(r#"{"time": 30, "saves": [{"key": "F9", "time": 30}, {"key": "F8", "time": 60}, {"key": "F7", "time": 2.5}]}"#);
+        // a bad length keeps the key and saves the whole buffer
+        let times: Vec<_> = config.saves.iter().map(|s| s.time).collect();
+        assert_eq!(times, [30, 30, 30]);
+        assert!(config.saves[2].key == key("F7"));
+        assert_eq!(problems.len(), 2, "{:?}", problems);
+    }
+
+    #[test]
+    fn saves_without_a_usable_entry_fall_back_to_key() {
+        for saves in ["[]", "5", r#"[{"time": 5}]"#] {
+            let (config, problems) = parse(&format!(r#"{{"key": "F6", "saves": {}}}"#, saves));
+            assert_eq!(config.saves.len(), 1, "{}", saves);
+            assert!(config.saves[0].key == key("F6") && config.saves[0].time == 10);
+            assert!(!problems.is_empty());
+        }
+    }
+
+    #[test]
+    fn monitors_by_number_name_and_all() {
+        let (config, problems) = parse(r#"{"monitor": [1, "HDMI-1", "all"]}"#);
+        assert!(problems.is_empty(), "{:?}", problems);
+        assert_eq!(config.monitors, [MonitorChoice::Index(1), MonitorChoice::Name("HDMI-1".to_string()), MonitorChoice::All]);
+        assert_eq!(parse(r#"{"monitor": 0.5}"#).1.len(), 1);
+    }
+}
@@ -0,0 +1,189 @@
+use crate::capture::PixelFormat;
+use crate::simd;
+
+// BT.709 in limited range, in 16 bit fixed point. Each chroma row sums to zero so grey stays
+// exactly 128.
+const Y_R: i32 = 11966;
+const Y_G: i32 = 40254;
+const Y_B: i32 = 4064;
+const U_R: i32 = -6596;
+const U_G: i32 = -22188;
+const U_B: i32 = 28784;
+const V_R: i32 = 28784;
+const V_G: i32 = -26145;
+const V_B: i32 = -2639;
+
+/// Turns captured BGRA into the 4:2:0 layout the encoder reads, so ffmpeg gets well under half
+/// the bytes and nothing left to convert. Odd sizes are padded to even by repeating the last
+/// row and column, which is what every 4:2:0 encoder needs anyway.
+pub struct Converter {
+    width: usize,
+    height: usize,
+    to: PixelFormat,
+}
+
+impl Converter {
+    pub fn new(from: PixelFormat, width: u32, height: u32, to: PixelFormat) -> Self {
+        // every capture source gives BGRA so far
+        assert_eq!(from, PixelFormat::Bgra, "can only convert from BGRA");
+        assert!(matches!(to, PixelFormat::Nv12 | PixelFormat::I420), "can only convert to NV12 or I420");
+        Converter { width: width as usize, height: height as usize, to }
+    }
+
+    pub fn convert(&self, bgra: &[u8]) -> Vec<u8> {
+        let (width, height) = geometry(self.width as u32, self.height as u32);
+        let mut out = vec![0u8; self.to.frame_size(width, height)];
+        simd::wide(#[inline(always)] || convert(bgra, self.width, self.height, self.to, &mut out));
+        out
+    }
+}
+
+/// The size of the converted picture.
+pub fn geometry(width: u32, height: u32) -> (u32, u32) {
+    (padded(width as usize) as u32, padded(height as usize) as u32)
+}
+
+fn padded(n: usize) -> usize {
+    n + (n & 1)
+}
+
+#[inline(always)]
+fn convert(bgra: &[u8], width: usize, height: usize, to: PixelFormat, out: &mut [u8]) {
+    let (out_width, out_height) = (padded(width), padded(height));
+    let chroma_width = out_width / 2;
+    let (luma, chroma) = out.split_at_mut(out_width * out_height);
+    let (mut u_row, mut v_row) = (vec![0u8; chroma_width], vec![0u8; chroma_width]);
+
+    for (pair, luma) in luma.chunks_exact_mut(out_width * 2).enumerate() {
+        let top = 2 * pair;
+        let bottom = (top + 1).min(height - 1);
+        let (luma_top, luma_bottom) = luma.split_at_mut(out_width);
+        rows(
+            &bgra[top * width * 4..][..width * 4],
+            &bgra[bottom * width * 4..][..width * 4],
+            luma_top,
+            luma_bottom,
+            &mut u_row,
+            &mut v_row,
+        );
+
+        match to {
+            PixelFormat::Nv12 => {
+                let uv = &mut chroma[pair * chroma_width * 2..][..chroma_width * 2];
+                for ((uv, &u), &v) in uv.chunks_exact_mut(2).zip(&u_row).zip(&v_row) {
+                    uv[0] = u;
+                    uv[1] = v;
+                }
+            },
+            _ => {
+                let plane = chroma_width * (out_height / 2);
+                chroma[pair * chroma_width..][..chroma_width].copy_from_slice(&u_row);
+                chroma[plane + pair * chroma_width..][..chroma_width].copy_from_slice(&v_row);
+            },
+        }
+    }
+}
+
+// two picture rows into their two luma rows and one row of each chroma, averaged over 2x2
+// blocks. Each pass is a plain loop over fixed size chunks, which the compiler vectorizes.
+#[inline(always)]
+fn rows(top: &[u8], bottom: &[u8], luma_top: &mut [u8], luma_bottom: &mut [u8], u: &mut [u8], v: &mut [u8]) {
+    for (row, luma) in [(top, luma_top), (bottom, luma_bottom)] {
+        for (pixel, luma) in row.chunks_exact(4).zip(luma.iter_mut()) {
+            *luma = y(pixel[2] as i32, pixel[1] as i32, pixel[0] as i32);
+        }
+        // an odd width repeats the last pixel into the padding column
+        if row.len() / 4 < luma.len() {
+            luma[luma.len() - 1] = luma[luma.len() - 2];
+        }
+    }
+
+    for (((top, bottom), u), v) in top.chunks_exact(8).zip(bottom.chunks_exact(8)).zip(u.iter_mut()).zip(v.iter_mut()) {
+        let blue = top[0] as i32 + top[4] as i32 + bottom[0] as i32 + bottom[4] as i32;
+        let green = top[1] as i32 + top[5] as i32 + bottom[1] as i32 + bottom[5] as i32;
+        let red = top[2] as i32 + top[6] as i32 + bottom[2] as i32 + bottom[6] as i32;
+        (*u, *v) = uv(red, green, blue);
+    }
+    if !top.len().is_multiple_of(8) {
+        let (top, bottom) = (&top[top.len() - 4..], &bottom[bottom.len() - 4..]);
+        let blue = 2 * (top[0] as i32 + bottom[0] as i32);
+        let green = 2 * (top[1] as i32 + bottom[1] as i32);
+        let red = 2 * (top[2] as i32 + bottom[2] as i32);
+        (u[u.len() - 1], v[v.len() - 1]) = uv(red, green, blue);
+    }
+}
+
+#[inline(always)]
+fn y(red: i32, green: i32, blue: i32) -> u8 {
+    ((Y_R * red + Y_G * green + Y_B * blue + (16 << 16) + (1 << 15)) >> 16) as u8
+}
+
+// from the sums of four pixels, so two more bits to shift away
+#[inline(always)]
+fn uv(red: i32, green: i32, blue: i32) -> (u8, u8) {
+    let u = (U_R * red + U_G * green + U_B * blue + (128 << 18) + (1 << 17)) >> 18;
+    let v = (V_R * red + V_G * green + V_B * blue + (128 << 18) + (1 << 17)) >> 18;
+    (u as u8, v as u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn solid(red: u8, green: u8, blue: u8, to: PixelFormat) -> Vec<u8> {
+        let bgra = [blue, green, red, 255].repeat(4);
+        Converter::new(PixelFormat::Bgra, 2, 2, to).convert(&bgra)
+    }
+
+    #[test]
+    fn bt709_limited_range() {
+        // Y, U, V worked out in floating point and rounded
+        let expected = [
+            ((0, 0, 0), [16, 128, 128]),
+            ((255, 255, 255), [235, 128, 128]),
+            ((128, 128, 128), [126, 128, 128]),
+            ((255, 0, 0), [63, 102, 240]),
+            ((0, 255, 0), [173, 42, 26]),
+            ((0, 0, 255), [32, 240, 118]),
+        ];
+        for ((red, green, blue), [y, u, v]) in expected {
+            assert_eq!(solid(red, green, blue, PixelFormat::I420), [y, y, y, y, u, v], "{} {} {}", red, green, blue);
+            assert_eq!(solid(red, green, blue, PixelFormat::Nv12), [y, y, y, y, u, v], "{} {} {}", red, green, blue);
+        }
+    }
+
+    #[test]
+    fn odd_sizes_repeat_the_last_row_and_column() {
+        // 3x3 of black with a red bottom right corner
+        let mut bgra = [0, 0, 0, 255].repeat(9);
+        bgra[8 * 4..][..3].copy_from_slice(&[0, 0, 255]);
+        assert_eq!(geometry(3, 3), (4, 4));
+        let out = Converter::new(PixelFormat::Bgra, 3, 3, PixelFormat::I420).convert(&bgra);
+        assert_eq!(out.len(), 4 * 4 + 2 * 2 * 2);
+
+        #[rustfmt::skip]
+        let luma = [
+            16, 16, 16, 16,
+            16, 16, 16, 16,
+            16, 16, 63, 63,
+            16, 16, 63, 63,
+        ];
+        assert_eq!(out[..16], luma);
+        // the corner's chroma block is the red pixel four times over
+        assert_eq!(out[16..20], [128, 128, 128, 102]);
+        assert_eq!(out[20..24], [128, 128, 128, 240]);
+    }
+
+    #[test]
+    fn nv12_interleaves_chroma() {
+        let mut bgra = [0, 0, 0, 255].repeat(8);
+        // the right 2x2 block of a 4x2 picture is blue
+        for i in [2, 3, 6, 7] {
+            bgra[i * 4] = 255;
+        }
+        let nv12 = Converter::new(PixelFormat::Bgra, 4, 2, PixelFormat::Nv12).convert(&bgra);
+        assert_eq!(nv12[8..], [128, 128, 240, 118]);
+        let i420 = Converter::new(PixelFormat::Bgra, 4, 2, PixelFormat::I420).convert(&bgra);
+        assert_eq!(i420[8..], [128, 240, 128, 118]);
+    }
+}
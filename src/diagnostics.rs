@@ -0,0 +1,159 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Write};
+use std::process::ChildStderr;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use time::{OffsetDateTime, format_description};
+
+pub const LOG_PATH: &str = "moment.log";
+const LOG_LIMIT: u64 = 1024 * 1024;
+// moment.log.1 is the newest old one
+const LOG_KEEP: usize = 3;
+const PROGRESS_EVERY: Duration = Duration::from_secs(30);
+
+/// The options that make ffmpeg's stderr something `watch` can read: a `[level]` tag on every
+/// message and progress lines even though info messages are left out.
+pub const FFMPEG_ARGS: [&str; 4] = ["-hide_banner", "-loglevel", "level+warning", "-stats"];
+
+static LOG: Mutex<Option<File>> = Mutex::new(None);
+
+/// Appends a line to `moment.log`, moving it aside once it gets big.
+pub fn log(label: &str, line: &str) {
+    let mut log = LOG.lock().unwrap();
+    if log.as_ref().and_then(|f| f.metadata().ok()).map(|m| m.len() >= LOG_LIMIT).unwrap_or(false) {
+        *log = None;
+        rotate();
+    }
+    if log.is_none() {
+        *log = OpenOptions::new().create(true).append(true).open(LOG_PATH).ok();
+    }
+    if let Some(file) = log.as_mut() {
+        let _ = writeln!(file, "{} [{}] {}", timestamp(), label, line);
+    }
+}
+
+fn rotate() {
+    for i in (1..LOG_KEEP).rev() {
+        let _ = fs::rename(format!("{}.{}", LOG_PATH, i), format!("{}.{}", LOG_PATH, i + 1));
+    }
+    let _ = fs::rename(LOG_PATH, format!("{}.1", LOG_PATH));
+}
+
+fn timestamp() -> String {
+    let now = OffsetDateTime::now_utc();
+    format_description::parse("[year]-[month]-[day] [hour]:[minute]:[second]")
+        .ok()
+        .and_then(|fmt| now.format(&fmt).ok())
+        .unwrap_or_default()
+}
+
+#[derive(Default)]
+struct Seen {
+    error: Option<String>,
+    // a warning only stands in for the error until a real one shows up
+    error_is_warning: bool,
+    progress: Option<String>,
+}
+
+/// What one ffmpeg process has said so far. Everything also goes to `moment.log`.
+pub struct Diagnostics {
+    seen: Arc<Mutex<Seen>>,
+    reader: Option<JoinHandle<()>>,
+}
+
+impl Diagnostics {
+    /// The last error or warning ffmpeg printed.
+    pub fn last_error(&self) -> Option<String> {
+        self.seen.lock().unwrap().error.clone()
+    }
+
+    /// The last progress line, like `frame= 1234 fps= 60 ... speed=1x`. Complete after `finish`.
+    pub fn progress(&self) -> Option<String> {
+        self.seen.lock().unwrap().progress.clone()
+    }
+
+    /// Waits for ffmpeg to close stderr, then returns the last error. Call after the process exited.
+    pub fn finish(&mut self) -> Option<String> {
+        if let Some(reader) = self.reader.take() {
+            let _ = reader.join();
+        }
+        self.last_error()
+    }
+}
+
+/// Reads `stderr` on its own thread so ffmpeg never blocks on a full pipe.
+pub fn watch(stderr: ChildStderr, label: &str) -> Diagnostics {
+    let seen = Arc::new(Mutex::new(Seen::default()));
+    let label = label.to_string();
+    let reader = {
+        let seen = seen.clone();
+        thread::spawn(move || read_stderr(stderr, &label, &seen))
+    };
+    Diagnostics { seen, reader: Some(reader) }
+}
+
+fn read_stderr(mut stderr: ChildStderr, label: &str, seen: &Mutex<Seen>) {
+    let mut buf = [0u8; 4096];
+    let mut line = Vec::new();
+    let mut last_logged_progress: Option<Instant> = None;
+
+    loop {
+        let n = match stderr.read(&mut buf) {
+            Ok(0) | Err(_) => break,
+            Ok(n) => n,
+        };
+        // progress lines end in \r so they overwrite each other on a terminal
+        for &b in &buf[..n] {
+            if b != b'\n' && b != b'\r' {
+                line.push(b);
+                continue;
+            }
+            let text = String::from_utf8_lossy(&line).trim().to_string();
+            line.clear();
+            if text.is_empty() {
+                continue;
+            }
+
+            if is_progress(&text) {
+                if last_logged_progress.is_none_or(|t| t.elapsed() >= PROGRESS_EVERY) {
+                    log(label, &text);
+                    last_logged_progress = Some(Instant::now());
+                }
+                seen.lock().unwrap().progress = Some(text);
+            } else {
+                log(label, &text);
+                if let Some((message, warning)) = problem(&text) {
+                    let mut seen = seen.lock().unwrap();
+                    if !warning || seen.error.is_none() || seen.error_is_warning {
+                        seen.error = Some(message);
+                        seen.error_is_warning = warning;
+                    }
+                }
+            }
+        }
+    }
+
+    let text = String::from_utf8_lossy(&line).trim().to_string();
+    if !text.is_empty() {
+        log(label, &text);
+    }
+}
+
+fn is_progress(line: &str) -> bool {
+    line.starts_with("frame=") || line.starts_with("size=")
+}
+
+// "[h264_nvenc @ 000001] [error] No capable devices found" comes back as
+// "h264_nvenc: No capable devices found", and whether it was only a warning
+fn problem(line: &str) -> Option<(String, bool)> {
+    let tag = ["[fatal] ", "[error] ", "[warning] "].into_iter().find(|tag| line.contains(tag))?;
+    let (context, message) = line.split_once(tag)?;
+    let component = context.trim().trim_start_matches('[').split(" @ ").next().unwrap_or("").trim_end_matches(']');
+    let message = if component.is_empty() {
+        message.trim().to_string()
+    } else {
+        format!("{}: {}", component, message.trim())
+    };
+    Some((message, tag == "[warning] "))
+}
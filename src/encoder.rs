@@ -0,0 +1,312 @@
+use crate::capture::PixelFormat;
+use crate::nut::{NutWriter, StreamKind, TIME_BASE};
+use crate::platform;
+use std::process::Stdio;
+use std::str::FromStr;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Codec {
+    H264,
+    Hevc,
+    Av1,
+}
+
+impl Codec {
+    /// Highest quality value the codec's quantizer goes to.
+    pub fn max_quality(self) -> u32 {
+        if self == Codec::Av1 { 63 } else { 51 }
+    }
+
+    // ffmpeg writes hevc into mp4 as hev1, which quicktime and most phones refuse to play
+    pub fn mp4_tag(self) -> Option<&'static str> {
+        if self == Codec::Hevc { Some("hvc1") } else { None }
+    }
+}
+
+/// Which family of ffmpeg encoder, each has its own spelling for presets and rate control.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Backend {
+    X264,
+    X265,
+    SvtAv1,
+    Amf,
+    Nvenc,
+    Qsv,
+}
+
+pub const ENCODERS: &[(&str, Codec, Backend)] = &[
+    ("libx264", Codec::H264, Backend::X264),
+    ("h264_amf", Codec::H264, Backend::Amf),
+    ("h264_nvenc", Codec::H264, Backend::Nvenc),
+    ("h264_qsv", Codec::H264, Backend::Qsv),
+    ("libx265", Codec::Hevc, Backend::X265),
+    ("hevc_amf", Codec::Hevc, Backend::Amf),
+    ("hevc_nvenc", Codec::Hevc, Backend::Nvenc),
+    ("hevc_qsv", Codec::Hevc, Backend::Qsv),
+    ("libsvtav1", Codec::Av1, Backend::SvtAv1),
+    ("av1_amf", Codec::Av1, Backend::Amf),
+    ("av1_nvenc", Codec::Av1, Backend::Nvenc),
+    ("av1_qsv", Codec::Av1, Backend::Qsv),
+];
+
+// what the old integer "encoder" setting meant
+pub const LEGACY_ENCODERS: [&str; 4] = ["libx264", "h264_amf", "h264_nvenc", "h264_qsv"];
+
+pub fn lookup(name: &str) -> Option<(Codec, Backend)> {
+    ENCODERS.iter().find(|(n, _, _)| *n == name).map(|&(_, c, b)| (c, b))
+}
+
+pub fn backend(name: &str) -> Option<Backend> {
+    lookup(name).map(|(_, b)| b)
+}
+
+pub fn codec(name: &str) -> Codec {
+    lookup(name).map(|(c, _)| c).unwrap_or(Codec::H264)
+}
+
+const TEST_FRAMES: usize = 3;
+
+/// Names of the encoders the ffmpeg build has, empty if ffmpeg couldn't be asked.
+pub fn available() -> Vec<String> {
+    let output = match platform::ffmpeg().args(["-hide_banner", "-encoders"]).stderr(Stdio::null()).output() {
+        Ok(output) => output,
+        Err(_) => return Vec::new(),
+    };
+    // " V....D libx264              libx264 H.264 / AVC ..."
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| {
+            let mut parts = line.split_whitespace();
+            let flags = parts.next()?;
+            let name = parts.next()?;
+            (flags.len() == 6 && name != "=").then(|| name.to_string())
+        })
+        .collect()
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum RateControl {
+    /// Constant bitrate at `kbps`.
+    Cbr,
+    /// Variable bitrate averaging `kbps`.
+    Vbr,
+    /// Constant quality, bitrate follows the picture.
+    Crf,
+    /// Fixed quantizer.
+    Cqp,
+}
+
+impl FromStr for RateControl {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "cbr" => Ok(RateControl::Cbr),
+            "vbr" => Ok(RateControl::Vbr),
+            "crf" => Ok(RateControl::Crf),
+            "cqp" => Ok(RateControl::Cqp),
+            _ => Err(format!("must be \"cbr\", \"vbr\", \"crf\" or \"cqp\", not \"{}\"", s)),
+        }
+    }
+}
+
+/// One entry of the `encoders` table in `ack.cfg`.
+#[derive(Clone, PartialEq, Debug)]
+pub struct EncoderSettings {
+    pub name: String,
+    pub preset: Option<String>,
+    pub rate_control: RateControl,
+    /// CRF/CQ/QP value for the quality based modes.
+    pub quality: u32,
+    /// Keyframe interval in frames, one second when unset.
+    pub gop: Option<u32>,
+    pub profile: Option<String>,
+}
+
+impl EncoderSettings {
+    pub fn new(name: &str) -> Self {
+        EncoderSettings {
+            name: name.to_string(),
+            preset: None,
+            rate_control: RateControl::Vbr,
+            quality: 23,
+            gop: None,
+            profile: None,
+        }
+    }
+
+    pub fn codec(&self) -> Codec {
+        codec(&self.name)
+    }
+
+    /// What to try next when this HEVC or AV1 encoder doesn't work: H.264 on the same hardware,
+    /// or libx264 for the software ones.
+    pub fn h264_fallback(&self) -> Option<EncoderSettings> {
+        let backend = backend(&self.name)?;
+        let name = match backend {
+            Backend::Amf => "h264_amf",
+            Backend::Nvenc => "h264_nvenc",
+            Backend::Qsv => "h264_qsv",
+            Backend::X264 | Backend::X265 | Backend::SvtAv1 => "libx264",
+        };
+        if name == self.name {
+            return None;
+        }
+
+        let mut fallback = EncoderSettings::new(name);
+        fallback.rate_control = self.rate_control;
+        fallback.quality = self.quality.min(Codec::H264.max_quality());
+        fallback.gop = self.gop;
+        // hardware presets mean the same for every codec, profiles never carry over
+        if !matches!(backend, Backend::X264 | Backend::X265 | Backend::SvtAv1) {
+            fallback.preset = self.preset.clone();
+        }
+        Some(fallback)
+    }
+
+    /// What the frames are converted to for this encoder, so ffmpeg can hand them over as they are.
+    pub fn input_format(&self) -> PixelFormat {
+        // qsv only takes nv12 and refuses yuv420p outright
+        if backend(&self.name) == Some(Backend::Qsv) { PixelFormat::Nv12 } else { PixelFormat::I420 }
+    }
+
+    /// The ffmpeg output options for this encoder, `-c:v` included.
+    pub fn ffmpeg_args(&self, kbps: i32, fps: i32) -> Vec<String> {
+        let backend = backend(&self.name).unwrap_or(Backend::X264);
+        let rate = format!("{}k", kbps);
+        let peak = format!("{}k", kbps * 3 / 2);
+        let buf = format!("{}k", kbps * 2);
+        let q = self.quality.to_string();
+
+        let mut args: Vec<String> = vec!["-c:v".into(), self.name.clone()];
+        let mut push = |a: &[&str]| args.extend(a.iter().map(|s| s.to_string()));
+
+        match backend {
+            Backend::X264 | Backend::X265 => {
+                push(&["-preset", self.preset.as_deref().unwrap_or("veryfast"), "-tune", "zerolatency"]);
+                match self.rate_control {
+                    RateControl::Cbr => push(&["-b:v", &rate, "-minrate", &rate, "-maxrate", &rate, "-bufsize", &rate]),
+                    RateControl::Vbr => push(&["-b:v", &rate]),
+                    RateControl::Crf => push(&["-crf", &q]),
+                    RateControl::Cqp if backend == Backend::X265 => push(&["-x265-params", &format!("qp={}", q)]),
+                    RateControl::Cqp => push(&["-qp", &q]),
+                }
+            },
+            Backend::SvtAv1 => {
+                // presets are numbers here, 0 is slowest and 13 fastest
+                push(&["-preset", self.preset.as_deref().unwrap_or("10")]);
+                match self.rate_control {
+                    // svt only does real cbr with the low delay prediction structure
+                    RateControl::Cbr => push(&["-b:v", &rate, "-svtav1-params", "rc=2:pred-struct=1"]),
+                    RateControl::Vbr => push(&["-b:v", &rate]),
+                    RateControl::Crf => push(&["-crf", &q]),
+                    RateControl::Cqp => push(&["-qp", &q]),
+                }
+            },
+            Backend::Nvenc => {
+                push(&["-preset", self.preset.as_deref().unwrap_or("p4"), "-zerolatency", "1"]);
+                match self.rate_control {
+                    RateControl::Cbr => push(&["-rc", "cbr", "-b:v", &rate]),
+                    RateControl::Vbr => push(&["-rc", "vbr", "-b:v", &rate, "-maxrate", &peak, "-bufsize", &buf]),
+                    RateControl::Crf => push(&["-rc", "vbr", "-cq", &q, "-b:v", "0"]),
+                    RateControl::Cqp => push(&["-rc", "constqp", "-qp", &q]),
+                }
+            },
+            Backend::Amf => {
+                push(&["-usage", "lowlatency", "-quality", self.preset.as_deref().unwrap_or("speed")]);
+                match self.rate_control {
+                    RateControl::Cbr => push(&["-rc", "cbr", "-b:v", &rate]),
+                    RateControl::Vbr => push(&["-rc", "vbr_peak", "-b:v", &rate, "-maxrate", &peak]),
+                    RateControl::Crf => push(&["-rc", "qvbr", "-qvbr_quality_level", &q]),
+                    // only the h264 one has b-frames to set a quantizer for
+                    RateControl::Cqp if self.codec() == Codec::H264 => push(&["-rc", "cqp", "-qp_i", &q, "-qp_p", &q, "-qp_b", &q]),
+                    RateControl::Cqp => push(&["-rc", "cqp", "-qp_i", &q, "-qp_p", &q]),
+                }
+            },
+            Backend::Qsv => {
+                push(&["-preset", self.preset.as_deref().unwrap_or("veryfast")]);
+                match self.rate_control {
+                    // qsv picks CBR by itself when maxrate equals the bitrate
+                    RateControl::Cbr => push(&["-b:v", &rate, "-maxrate", &rate]),
+                    RateControl::Vbr => push(&["-b:v", &rate, "-maxrate", &peak]),
+                    RateControl::Crf => push(&["-global_quality", &q]),
+                    RateControl::Cqp => push(&["-q:v", &q]),
+                }
+            },
+        }
+
+        if let Some(profile) = &self.profile {
+            push(&["-profile:v", profile]);
+        }
+        // frames stop coming while the screen sits still, so -g alone would leave clips without a
+        // keyframe to start from, the time based one keeps them coming either way
+        let gop = self.gop.unwrap_or(fps as u32);
+        let interval = gop as f64 / fps as f64;
+        push(&["-g", &gop.to_string(), "-force_key_frames", &format!("expr:gte(t,n_forced*{})", interval)]);
+        args
+    }
+}
+
+/// Encodes a few blank frames of the real size with `settings`, which is the only way to find out
+/// whether a hardware encoder actually has hardware behind it. The error is ffmpeg's last word.
+pub fn test_encode(settings: &EncoderSettings, width: u32, height: u32, fps: i32, kbps: i32) -> Result<(), String> {
+    let pixel_format = settings.input_format();
+    let mut child = platform::ffmpeg()
+        .args(["-hide_banner", "-loglevel", "error", "-f", "nut", "-i", "-"])
+        .args(settings.ffmpeg_args(kbps, fps))
+        .args(["-pix_fmt", pixel_format.ffmpeg_name(), "-frames:v", &TEST_FRAMES.to_string(), "-f", "null", "-"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| e.to_string())?;
+
+    let frame = vec![0u8; pixel_format.frame_size(width, height)];
+    let stream = StreamKind::Video { fourcc: pixel_format.nut_fourcc(), width, height };
+    // a failed write only means ffmpeg gave up already, its stderr says why
+    if let Ok(mut nut) = NutWriter::new(child.stdin.take().unwrap(), &[stream]) {
+        for i in 0..TEST_FRAMES {
+            if nut.write_frame(0, i as u64 * TIME_BASE / fps as u64, &frame).is_err() {
+                break;
+            }
+        }
+    }
+
+    let output = child.wait_with_output().map_err(|e| e.to_string())?;
+    if output.status.success() {
+        return Ok(());
+    }
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    Err(stderr.lines().rev().find(|l| !l.trim().is_empty()).unwrap_or("ffmpeg failed without saying why").trim().to_string())
+}
+
+/// The first of `candidates` this ffmpeg has and that passes `test`, along with why each one
+/// before it was passed over. The last candidate is used if none of them work.
+pub fn pick(
+    candidates: Vec<EncoderSettings>,
+    available: &[String],
+    test: impl Fn(&EncoderSettings) -> Result<(), String>,
+) -> (EncoderSettings, Vec<String>) {
+    let mut skipped: Vec<String> = Vec::new();
+    let mut tried: Vec<String> = Vec::new();
+    let mut last = None;
+    for candidate in candidates {
+        if tried.contains(&candidate.name) {
+            continue;
+        }
+        tried.push(candidate.name.clone());
+
+        // an empty list means ffmpeg couldn't be asked, let the test encode decide
+        let result = if !available.is_empty() && !available.contains(&candidate.name) {
+            Err("not in this ffmpeg build".to_string())
+        } else {
+            test(&candidate)
+        };
+        match result {
+            Ok(()) => return (candidate, skipped),
+            Err(e) => skipped.push(format!("{}: {}", candidate.name, e)),
+        }
+        last = Some(candidate);
+    }
+    (last.unwrap_or_else(|| EncoderSettings::new("libx264")), skipped)
+}
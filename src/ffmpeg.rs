@@ -0,0 +1,164 @@
+use crate::encoder;
+use crate::platform;
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::OnceLock;
+
+#[cfg(windows)]
+const EXE_NAME: &str = "ffmpeg.exe";
+#[cfg(not(windows))]
+const EXE_NAME: &str = "ffmpeg";
+
+// -cluster_time_limit and the encoder options moment passes all work from 4.0 on
+const MIN_VERSION: (u32, u32) = (4, 0);
+
+static FOUND: OnceLock<PathBuf> = OnceLock::new();
+
+/// The ffmpeg every `platform::ffmpeg()` command runs, `ffmpeg` on PATH until `use_path` is called.
+pub fn path() -> &'static Path {
+    FOUND.get().map(PathBuf::as_path).unwrap_or(Path::new(EXE_NAME))
+}
+
+pub fn use_path(path: PathBuf) {
+    let _ = FOUND.set(path);
+}
+
+/// Looks for ffmpeg at `configured` (when set), next to moment's own executable, then on PATH.
+/// A bare name like "ffmpeg" or "ffmpeg7" in `configured` is looked up on PATH.
+pub fn find(configured: &str) -> Result<PathBuf, String> {
+    if !configured.is_empty() {
+        let path = PathBuf::from(configured);
+        if path.components().count() == 1
+            && !path.exists()
+            && let Some(path) = on_path(configured)
+        {
+            return Ok(path);
+        }
+        // a folder is fine too, people tend to point at where they unpacked it
+        let path = if path.is_dir() { path.join(EXE_NAME) } else { path };
+        if path.is_file() {
+            return Ok(path);
+        }
+        return Err(format!(
+            "ffmpeg was not found at \"{}\", the \"ffmpeg\" setting in ack.cfg.\n\nPoint it at {}, give the name it has on PATH, or remove the setting to search next to moment and on PATH.",
+            configured, EXE_NAME
+        ));
+    }
+
+    let mut looked = Vec::new();
+    if let Ok(exe) = env::current_exe()
+        && let Some(dir) = exe.parent()
+    {
+        let path = dir.join(EXE_NAME);
+        if path.is_file() {
+            return Ok(path);
+        }
+        looked.push(dir.display().to_string());
+    }
+
+    if let Some(path) = on_path(EXE_NAME) {
+        return Ok(path);
+    }
+    looked.push("every folder on PATH".to_string());
+
+    Err(format!(
+        "moment needs ffmpeg and could not find {}.\n\nLooked in:\n{}\n\nPut {} next to moment, install it on PATH, or set \"ffmpeg\" in ack.cfg to where it is.",
+        EXE_NAME,
+        looked.join("\n"),
+        EXE_NAME
+    ))
+}
+
+// the first `name` in a folder on PATH, with ".exe" added on Windows when it has no extension
+fn on_path(name: &str) -> Option<PathBuf> {
+    let name = match Path::new(name).extension() {
+        None if cfg!(windows) => format!("{}.exe", name),
+        _ => name.to_string(),
+    };
+    let dirs = env::var_os("PATH").map(|p| env::split_paths(&p).collect::<Vec<_>>()).unwrap_or_default();
+    dirs.into_iter().map(|dir| dir.join(&name)).find(|path| path.is_file())
+}
+
+/// Makes sure the ffmpeg from `use_path` runs, is new enough and can read and write what moment
+/// uses. Returns its version string.
+pub fn check(audio: bool) -> Result<String, String> {
+    let path = path();
+    let run = |args: &[&str]| -> Result<String, String> {
+        let output = platform::ffmpeg()
+            .arg("-hide_banner")
+            .args(args)
+            .stdin(Stdio::null())
+            .stderr(Stdio::null())
+            .output()
+            .map_err(|e| format!("{} could not be started: {}", path.display(), e))?;
+        if !output.status.success() {
+            return Err(format!("{} exited with {} when asked for {}", path.display(), output.status, args.join(" ")));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    };
+
+    // "ffmpeg version 6.1.1-essentials_build-www.gyan.dev Copyright ..."
+    let version_text = run(&["-version"])?;
+    let version = version_text
+        .lines()
+        .next()
+        .and_then(|l| l.strip_prefix("ffmpeg version "))
+        .and_then(|l| l.split_whitespace().next())
+        .unwrap_or("unknown")
+        .to_string();
+    // git builds are named like N-113000-g1234abcd and are always new enough
+    if let Some((major, minor)) = parse_version(&version)
+        && (major, minor) < MIN_VERSION
+    {
+        return Err(format!(
+            "{} is ffmpeg {}, moment needs {}.{} or newer.",
+            path.display(),
+            version,
+            MIN_VERSION.0,
+            MIN_VERSION.1
+        ));
+    }
+
+    let formats = run(&["-formats"])?;
+    let mut missing = Vec::new();
+    let need = [('D', "nut"), ('D', "matroska"), ('E', "matroska"), ('E', "mp4"), ('E', "null")];
+    for (flag, name) in need {
+        if !has_format(&formats, flag, name) {
+            missing.push(format!("{} {}", if flag == 'D' { "reading" } else { "writing" }, name));
+        }
+    }
+    if audio && !encoder::available().iter().any(|e| e == "aac") {
+        missing.push("the aac encoder".to_string());
+    }
+    if !missing.is_empty() {
+        return Err(format!(
+            "{} (ffmpeg {}) can't do everything moment needs, it is missing:\n{}\n\nA full build like the one linked in the readme has all of it.",
+            path.display(),
+            version,
+            missing.join("\n")
+        ));
+    }
+    Ok(version)
+}
+
+fn parse_version(version: &str) -> Option<(u32, u32)> {
+    let version = version.strip_prefix('n').unwrap_or(version);
+    let mut parts = version.split(['.', '-']);
+    let major = parts.next()?.parse().ok()?;
+    let minor = parts.next().and_then(|p| p.parse().ok()).unwrap_or(0);
+    Some((major, minor))
+}
+
+// lines look like " DE matroska,webm   Matroska / WebM", the flags column may also hold a 'd' for devices
+fn has_format(formats: &str, flag: char, name: &str) -> bool {
+    formats.lines().any(|line| {
+        let mut parts = line.split_whitespace();
+        match (parts.next(), parts.next()) {
+            (Some(flags), Some(names)) => {
+                flags.chars().all(|c| "DEd.".contains(c)) && flags.contains(flag) && names.split(',').any(|n| n == name)
+            },
+            _ => false,
+        }
+    })
+}
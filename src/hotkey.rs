@@ -0,0 +1,67 @@
+use device_query::Keycode;
+use std::str::FromStr;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Action {
+    /// Clip length in seconds.
+    SaveClip(i32),
+    Quit,
+    Pause,
+}
+
+/// A set of keys held together, written as `LControl+LShift+F10`.
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct Chord(Vec<Keycode>);
+
+impl FromStr for Chord {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut keys = Vec::new();
+        for token in s.split('+').map(str::trim) {
+            if token.is_empty() {
+                return Err(format!("Empty key in \"{}\"", s));
+            }
+            let key = Keycode::from_str(token).map_err(|_| format!("Unknown key \"{}\" in \"{}\"", token, s))?;
+            if !keys.contains(&key) {
+                keys.push(key);
+            }
+        }
+        Ok(Chord(keys))
+    }
+}
+
+impl Chord {
+    // exactly these keys, so F10 stays quiet while LControl+F10 is held
+    fn matches(&self, pressed: &[Keycode]) -> bool {
+        !self.0.is_empty() && pressed.len() == self.0.len() && self.0.iter().all(|k| pressed.contains(k))
+    }
+}
+
+pub struct Hotkeys {
+    bindings: Vec<(Chord, Action)>,
+    last: Vec<Keycode>,
+}
+
+impl Hotkeys {
+    pub fn new(bindings: Vec<(Chord, Action)>) -> Self {
+        Hotkeys { bindings, last: Vec::new() }
+    }
+
+    /// Swaps the bindings without forgetting which keys are held, so a held chord doesn't fire again.
+    pub fn rebind(&mut self, bindings: Vec<(Chord, Action)>) {
+        self.bindings = bindings;
+    }
+
+    /// Actions whose chord went down since the previous poll, each press fires once.
+    pub fn poll(&mut self, pressed: Vec<Keycode>) -> Vec<Action> {
+        let fired = self
+            .bindings
+            .iter()
+            .filter(|(chord, _)| chord.matches(&pressed) && !chord.matches(&self.last))
+            .map(|&(_, action)| action)
+            .collect();
+        self.last = pressed;
+        fired
+    }
+}
@@ -1,308 +1,616 @@
 // #![windows_subsystem = "windows"]
 
-use device_query::{DeviceQuery, DeviceState, Keycode};
-use dxgi_capture_rs::DXGIManager;
-use std::collections::HashMap;
-use std::fs::{self, File, remove_file};
-use std::io::Write;
-use std::os::windows::process::CommandExt;
-use std::process::{self, Command, Stdio};
-use std::str::FromStr;
+mod audio;
+mod capture;
+mod clip;
+mod config;
+mod convert;
+mod diagnostics;
+mod encoder;
+mod ffmpeg;
+mod hotkey;
+mod nut;
+mod platform;
+mod replay;
+mod scale;
+mod simd;
+mod supervisor;
+mod writer;
+
+use audio::AudioInput;
+use capture::{FrameSource, PixelFormat, Rect};
+use clip::ClipSaver;
+use config::{AudioConfig, AudioMode, Config};
+use convert::Converter;
+use device_query::{DeviceQuery, DeviceState};
+use encoder::EncoderSettings;
+use hotkey::{Action, Chord, Hotkeys};
+use nut::StreamKind;
+use replay::ReplayBuffer;
+use scale::Scaler;
+use supervisor::Supervisor;
+use writer::{FrameStats, FrameWriter, MediaClock, Sent, VIDEO_STREAM};
+use std::env;
+use std::process::{self, Stdio};
+use std::sync::atomic::Ordering;
 use std::sync::mpsc::{self, Receiver};
+use std::sync::{Arc, Mutex};
 use std::thread;
 use std::time::{Duration, Instant};
-use time::{OffsetDateTime, format_description};
-use tinyjson::JsonValue;
-use tray_icon::{
-    Icon, TrayIconBuilder,
-    menu::{Menu, MenuEvent, MenuItem},
-};
-use win_msgbox::Okay;
-use winit::event_loop::{ControlFlow, EventLoop};
-
-struct CONFIG {
-    key: Keycode,
-    fps: i32,
-    kbps: i32,
-    time: i32,
-    encoder: i32,
-}
-
-const DEFAULT_CFG: &str = "{\"time\":10,\"fps\":60,\"kbps\":10000,\"key\":\"F10\", \"encoder\": 0}";
-
-fn load_settings() -> Result<CONFIG, Box<dyn std::error::Error>> {
-    let cfg_string = fs::read_to_string("ack.cfg").unwrap_or_else(|_| {
-        let _ = fs::write("ack.cfg", DEFAULT_CFG);
-        String::from(DEFAULT_CFG)
-    });
 
-    let parsed: JsonValue = cfg_string.parse()?;
-    let map: &HashMap<String, JsonValue> = parsed.get().ok_or("Invalid JSON root")?;
-
-    let get_num = |k| {
-        map.get(k)
-            .and_then(|v| v.get::<f64>())
-            .copied()
-            .ok_or(format!("Missing/Invalid {}", k))
-    };
-    let get_str = |k| {
-        map.get(k)
-            .and_then(|v| v.get::<String>())
-            .cloned()
-            .ok_or(format!("Missing/Invalid {}", k))
-    };
-
-    let time = get_num("time")?;
-    let fps = get_num("fps")?;
-    let kbps = get_num("kbps")? * 1000.0;
-    let encoder = get_num("encoder")?;
-    let key = get_str("key")?;
-
-    Ok(CONFIG { key: Keycode::from_str(&key)?, fps: fps as i32, kbps: kbps as i32, time: time as i32, encoder: encoder.clamp(0., 3.) as i32 })
-}
+const SAVE_WAIT: Duration = Duration::from_secs(2);
+const STATS_EVERY: Duration = Duration::from_secs(60);
+const HEARTBEAT: Duration = Duration::from_millis(500);
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    unsafe {
-        use windows::Win32::Media::timeBeginPeriod;
-        timeBeginPeriod(1);
+    platform::begin_timer_period();
+
+    let (config, problems) = config::load();
+    if !problems.is_empty() {
+        platform::show_info(
+            "Configuration Error",
+            &format!("{}\n\nThe defaults are used for these settings.", problems.join("\n")),
+        );
+    }
+
+    if env::args().any(|a| a == "--list-monitors") {
+        for monitor in capture::monitors(&config.capture)? {
+            println!("{}", monitor);
+        }
+        return Ok(());
     }
 
-    let config: CONFIG = match load_settings() {
+    match ffmpeg::find(&config.ffmpeg) {
+        Ok(path) => ffmpeg::use_path(path),
         Err(e) => {
-            let _ = win_msgbox::information::<Okay>(&format!("{}\nResetting config.", e))
-                .title("Configuration Error")
-                .show();
-            let _ = fs::write("ack.cfg", DEFAULT_CFG);
-            load_settings()?
+            platform::show_error("ffmpeg Not Found", &e);
+            process::exit(1);
         },
-        Ok(c) => c,
-    };
-
-    let manager = DXGIManager::new(100)?;
-    let (width, height) = manager.geometry();
-    let device_state = DeviceState::new();
-
-    let _ = remove_file("buffer1.mp4");
-    let _ = remove_file("buffer0.mp4");
-
-    let (tx, rx) = mpsc::channel();
+    }
+    match ffmpeg::check(!config.audio.is_empty()) {
+        Ok(version) => eprintln!("Using ffmpeg {} at {}", version, ffmpeg::path().display()),
+        Err(e) => {
+            platform::show_error("ffmpeg Error", &e);
+            process::exit(1);
+        },
+    }
 
-    let tray_menu = Menu::new();
-    let quit_btn = MenuItem::new("Quit", true, None);
-    tray_menu.append(&quit_btn).unwrap();
-    let quit_id = quit_btn.into_id();
+    let monitors = capture::monitors(&config.capture)?;
+    for monitor in &monitors {
+        diagnostics::log("capture", &format!("monitor {}", monitor));
+    }
+    let picked = pick_monitors(&config, &monitors);
 
-    let icon_data = include_bytes!("../icon.raw").to_vec();
-    let tray_icon_icon = Icon::from_rgba(icon_data, 16, 16).unwrap();
-    let _tray_icon = TrayIconBuilder::new()
-        .with_menu(Box::new(tray_menu))
-        .with_tooltip("acid's clipping kit")
-        .with_icon(tray_icon_icon)
-        .build()
-        .unwrap();
+    let mut audio = Vec::new();
+    for a in &config.audio {
+        audio.push(AudioInput::start(audio::open(&a.source)?, a.volume));
+    }
+    let audio = Arc::new(audio);
+
+    // every monitor records on its own thread with its own buffer and encoder, they all see
+    // the same hotkeys so one press saves a clip of each
+    let (done_tx, done_rx) = mpsc::channel();
+    let mut quits = Vec::new();
+    for (slot, &index) in picked.iter().enumerate() {
+        let source = capture::open(&config.capture, index, config.synthetic_frames)?;
+        // a single monitor keeps the clip names they always had
+        let label = (picked.len() > 1).then(|| monitors[index].name.clone());
+        let (quit_tx, quit_rx) = mpsc::channel();
+        quits.push(quit_tx);
+        let (audio, config, done_tx) = (audio.clone(), config.clone(), done_tx.clone());
+        thread::spawn(move || {
+            // on Linux it holds an X connection that can't be sent between threads, so each
+            // recorder opens its own
+            let device_state = platform::device_state();
+            let record = recording_loop(quit_rx, source, &audio, device_state, config, slot, label);
+            let _ = done_tx.send(record.map_err(|e| e.to_string()));
+        });
+    }
 
-    let menu_channel = MenuEvent::receiver();
-    let event_loop = EventLoop::new().unwrap();
+    // the first recording to stop (quit key, finished source) takes the others with it
+    let recorder = {
+        let quits = quits.clone();
+        thread::spawn(move || {
+            for stopped in 0..quits.len() {
+                match done_rx.recv() {
+                    Ok(Err(e)) => {
+                        platform::show_error("Fatal Error", &e);
+                        process::exit(1);
+                    },
+                    _ if stopped == 0 => {
+                        for quit in &quits {
+                            let _ = quit.send(true);
+                        }
+                    },
+                    _ => {},
+                }
+            }
+            process::exit(0);
+        })
+    };
 
+    let (tx, rx) = mpsc::channel();
     thread::spawn(move || {
-        let record = recording_loop(rx, width as u32, height as u32, manager, device_state, config);
-        match record {
-            Err(e) => {
-                let _ = win_msgbox::error::<Okay>(&e.to_string())
-                    .title("Fatal Error")
-                    .show();
-                process::exit(1);
-            },
-            Ok(_) => {
-                process::exit(0);
+        if rx.recv().is_ok() {
+            for quit in &quits {
+                let _ = quit.send(true);
             }
         }
     });
 
-    #[allow(deprecated)]
-    event_loop
-        .run(move |_event, event_loop| {
-            event_loop.set_control_flow(ControlFlow::Wait);
-            if let Ok(event) = menu_channel.try_recv() {
-                if event.id == quit_id {
-                    let _ = tx.send(true);
-                    event_loop.exit();
-                }
-            }
-        })
-        .unwrap();
+    platform::run_tray(tx);
+    let _ = recorder.join();
 
     Ok(())
 }
 
+// `slot` numbers the monitors being recorded, `label` names this one when there are several
 fn recording_loop(
     rx: Receiver<bool>,
-    width: u32,
-    height: u32,
-    mut manager: DXGIManager,
-    device_state: DeviceState,
-    config: CONFIG
+    mut source: Box<dyn FrameSource>,
+    audio: &[AudioInput],
+    device_state: Option<DeviceState>,
+    mut config: Config,
+    slot: usize,
+    label: Option<String>,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let fps = config.fps;
-    let kbps = config.kbps;
-    let key_code = config.key;
-    let time_seg = config.time;
-    let enc_idx = config.encoder;
-
-    let encoder = match enc_idx {
-        0 => "libx264",
-        1 => "h264_amf",
-        2 => "h264_nvenc",
-        3 => "h264_qsv",
-        _ => "libx264",
+    let mut hotkeys = Hotkeys::new(bindings(&config));
+    let mut paused = false;
+    let mut watcher = config::Watcher::new();
+
+    let (mut screen_width, mut screen_height) = source.geometry();
+    let pixel_format = source.pixel_format();
+    let buffer = Arc::new(Mutex::new(ReplayBuffer::new(config.time as u64 * 1000)));
+    let mut saver = ClipSaver::new(container(&config), label.clone());
+    let log_label = match &label {
+        Some(label) => format!("encoder {}", label),
+        None => "encoder".to_string(),
     };
-
-    let frame_duration = Duration::from_nanos((1_000_000_000 / fps) as u64);
+    let available = encoder::available();
+    let mut pipeline = Pipeline::new(&config, &available, screen_width, screen_height, pixel_format);
+    saver.set_codec(pipeline.encoder.codec());
+    let mut supervisor = Supervisor::new();
+    let stats = Arc::new(FrameStats::default());
+    let mut last_stats_log = Instant::now();
+    // survives encoder restarts as long as the encoder takes the same format and size
+    let mut last_frame: Option<Arc<Vec<u8>>> = None;
 
     'main_loop: loop {
-        let mut child = Command::new("./ffmpeg.exe")
-            .args([
-                "-y",
-                "-f",
-                "rawvideo",
-                "-vcodec",
-                "rawvideo",
-                "-pixel_format",
-                "bgra",
-                "-video_size",
-                &format!("{}x{}", width, height),
-                "-framerate",
-                &fps.to_string(),
-                "-i",
-                "-",
-                "-c:v",
-                encoder,
-                "-tune",
-                "zerolatency",
-                "-b:v",
-                &format!("{}k", kbps / 1000),
+        let fps = config.fps;
+        let kbps = config.kbps;
+        let time_seg = config.time;
+        let frame_duration = Duration::from_nanos((1_000_000_000 / fps) as u64);
+
+        let mut args: Vec<String> = diagnostics::FFMPEG_ARGS.map(String::from).to_vec();
+        // the frames are converted to bt709 in tv range before they get here, tagging them keeps players from guessing
+        args.extend(["-color_range", "tv", "-colorspace", "bt709", "-color_primaries", "bt709", "-color_trc", "bt709"].map(String::from));
+        // everything arrives as one nut stream with capture timestamps, which -copyts keeps as they are
+        args.extend(["-y", "-copyts", "-f", "nut", "-i", "-"].map(String::from));
+
+        args.extend(["-map", "0:v:0"].map(String::from));
+        args.extend(audio_mapping(&config.audio, config.audio_mode));
+        if !audio.is_empty() {
+            args.extend(["-c:a", "aac", "-b:a", "160k"].map(String::from));
+        }
+
+        args.extend(pipeline.encoder.ffmpeg_args(kbps, fps));
+        args.extend(
+            [
                 "-pix_fmt",
-                "yuv420p",
+                pipeline.encoder.input_format().ffmpeg_name(),
                 "-f",
-                "segment",
-                "-segment_time",
-                &time_seg.to_string(),
-                "-segment_wrap",
-                "2",
-                "-reset_timestamps",
-                "1",
-                "buffer%d.mp4",
-            ])
-            .creation_flags(0x08000000)
+                "matroska",
+                "-cluster_time_limit",
+                "100",
+                "-",
+            ]
+            .map(String::from),
+        );
+
+        let mut child = platform::ffmpeg()
+            .args(&args)
             .stdin(Stdio::piped())
-            .stderr(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
             .spawn()?;
 
-        let mut stdin = child.stdin.take().unwrap();
+        let fourcc = pipeline.encoder.input_format().nut_fourcc();
+        let mut streams = vec![StreamKind::Video { fourcc, width: pipeline.out_width, height: pipeline.out_height }];
+        streams.extend(audio.iter().map(AudioInput::stream_kind));
+        let writer = FrameWriter::start(child.stdin.take().unwrap(), streams, stats.clone());
+        let clock = Arc::new(MediaClock::new(paused));
+        for (i, input) in audio.iter().enumerate() {
+            input.connect(slot, writer.sender(), VIDEO_STREAM + 1 + i, clock.clone());
+        }
+        let reader = replay::spawn_reader(child.stdout.take().unwrap(), buffer.clone());
+        let mut diagnostics = diagnostics::watch(child.stderr.take().unwrap(), &log_label);
+        supervisor.started();
 
         let mut next_frame_time = Instant::now();
-        let mut last_ckey_state = false;
         let mut last_key_poll = Instant::now();
+        let mut last_sent = Instant::now();
+        let mut pending_saves: Vec<(u64, i32, Instant)> = Vec::new();
+        let mut crashed = false;
+        let mut quitting = false;
 
-        loop {
+        'frames: loop {
             let now = Instant::now();
             if now < next_frame_time {
                 thread::sleep(next_frame_time - now);
             }
             next_frame_time += frame_duration;
+            // a slow capture skips the ticks it missed, the timestamps already tell how long each frame lasted
+            let behind = now.saturating_duration_since(next_frame_time);
+            next_frame_time += frame_duration * (behind.as_nanos() / frame_duration.as_nanos()) as u32;
 
-            if let Ok((data, _)) = manager.capture_frame_fast() {
-                if stdin.write_all(data.as_slice()).is_err() {
-                    break;
+            if paused {
+                // nothing reaches the encoder, the clip just skips the paused stretch
+            } else {
+                let frame = match source.capture_frame() {
+                    // the display mode changed, a game going fullscreen at its own resolution for one.
+                    // Everything is cut to the old size, so the pipeline starts over at the new one.
+                    Some(data) if data.len() != pixel_format.frame_size(screen_width, screen_height) => {
+                        let (new_width, new_height) = source.geometry();
+                        if (new_width, new_height) != (screen_width, screen_height) {
+                            diagnostics::log("capture", &format!("screen went from {}x{} to {}x{}", screen_width, screen_height, new_width, new_height));
+                            (screen_width, screen_height) = (new_width, new_height);
+                            pipeline = Pipeline::new(&config, &available, screen_width, screen_height, pixel_format);
+                            saver.set_codec(pipeline.encoder.codec());
+                            last_frame = None;
+                            break 'frames;
+                        }
+                        None
+                    },
+                    Some(data) => {
+                        stats.captured.fetch_add(1, Ordering::Relaxed);
+                        let frame = Arc::new(pipeline.process(data, screen_width));
+                        last_frame = Some(frame.clone());
+                        Some((frame, false))
+                    },
+                    None if source.is_finished() => {
+                        pending_saves.push((clock.now() / 1000, time_seg, Instant::now()));
+                        quitting = true;
+                        break 'frames;
+                    },
+                    // a still screen sends nothing, but keyframes have to keep coming for clips to start near their cut
+                    None if last_sent.elapsed() >= HEARTBEAT => last_frame.clone().map(|last| (last, true)),
+                    None => None,
+                };
+
+                if let Some((frame, duplicate)) = frame {
+                    match writer.send(frame, clock.now()) {
+                        Sent::Queued => {
+                            last_sent = Instant::now();
+                            if duplicate {
+                                stats.duplicated.fetch_add(1, Ordering::Relaxed);
+                            }
+                        },
+                        Sent::Dropped => {
+                            stats.dropped.fetch_add(1, Ordering::Relaxed);
+                        },
+                        Sent::Closed => {
+                            crashed = true;
+                            break 'frames;
+                        },
+                    }
                 }
             }
 
-            if last_key_poll.elapsed() >= Duration::from_millis(20) {
-                let keys = device_state.get_keys();
-                if keys.contains(&Keycode::F1) && keys.contains(&Keycode::LControl) {
-                    return Ok(());
-                }
+            // each clip ends at its press, wait for the encoder to hand us everything up to there
+            if !pending_saves.is_empty() {
+                let newest = buffer.lock().unwrap().newest();
+                pending_saves.retain(|&(end, seconds, pressed_at)| {
+                    let ready = newest >= end || pressed_at.elapsed() >= SAVE_WAIT;
+                    if ready {
+                        save_clip(&buffer, &mut saver, end, seconds);
+                    }
+                    !ready
+                });
+            }
 
-                let capture_pressed = keys.contains(&key_code);
-                if capture_pressed && !last_ckey_state {
-                    break;
+            if let Some(device_state) = &device_state
+                && last_key_poll.elapsed() >= Duration::from_millis(20)
+            {
+                for action in hotkeys.poll(device_state.get_keys()) {
+                    match action {
+                        Action::SaveClip(seconds) => pending_saves.push((clock.now() / 1000, seconds, Instant::now())),
+                        Action::Quit => {
+                            quitting = true;
+                            break 'frames;
+                        },
+                        Action::Pause => {
+                            paused = !paused;
+                            clock.set_paused(paused);
+                            platform::beep(if paused { 600 } else { 800 });
+                        },
+                    }
                 }
-                last_ckey_state = capture_pressed;
                 last_key_poll = Instant::now();
             }
 
+            // a static screen writes nothing, so a dead encoder isn't always noticed by the write
+            if matches!(child.try_wait(), Ok(Some(_))) {
+                crashed = true;
+                break;
+            }
+
+            if last_stats_log.elapsed() >= STATS_EVERY {
+                diagnostics::log("frames", &stats.summary());
+                last_stats_log = Instant::now();
+            }
+
+            if let Some(text) = watcher.poll() {
+                let (new, problems) = config::parse(&text);
+                if !problems.is_empty() {
+                    // every monitor reloads the same file, one popup about it is plenty
+                    if slot == 0 {
+                        platform::notify(
+                            "Configuration Error",
+                            &format!("{}\n\nThe previous settings are still in use.", problems.join("\n")),
+                        );
+                    }
+                } else {
+                    let encoder_changed = new.encoder != config.encoder || new.encoder_fallbacks != config.encoder_fallbacks;
+                    let resolution_changed = new.crop != config.crop || new.resolution != config.resolution;
+                    if apply_config(&mut config, new, &mut hotkeys, &buffer, &mut saver, slot == 0) {
+                        // a new size can be too big for a hardware encoder that took the old one
+                        if encoder_changed || resolution_changed {
+                            pipeline = Pipeline::new(&config, &available, screen_width, screen_height, pixel_format);
+                            saver.set_codec(pipeline.encoder.codec());
+                            last_frame = None;
+                        }
+                        break;
+                    }
+                }
+            }
+
             if rx.try_recv().is_ok() {
-                break 'main_loop;
+                quitting = true;
+                break;
             }
         }
 
-        drop(stdin);
-        let _ = child.wait();
-        save_final_clip()?;
+        // every way out comes through here, so a clip asked for just before quitting is still saved
+        let _ = writer.finish();
+        let status = child.wait();
+        let _ = reader.join();
+        let error = diagnostics.finish();
+
+        // everything the old encoder produced is in now, presses still waiting on it get what there is
+        for (end, seconds, _) in pending_saves {
+            save_clip(&buffer, &mut saver, end, seconds);
+        }
+
+        diagnostics::log("frames", &stats.summary());
+        if quitting {
+            eprintln!("Frames: {}", stats.summary());
+            break 'main_loop;
+        }
+        if crashed {
+            let status = status.map(|s| s.to_string()).unwrap_or_else(|e| e.to_string());
+            let reason = error.as_deref().unwrap_or("it gave no reason");
+            let restart = supervisor.crashed();
+            // how far it got tells a crash at startup from one after a while of recording
+            let progress = diagnostics.progress().unwrap_or_else(|| "nothing encoded".to_string());
+            diagnostics::log(&log_label, &format!("stopped ({}, {}), restart {} in {:?}", status, progress, restart.failures, restart.delay));
+            eprintln!("Encoder stopped ({}): {}, restarting in {:?}", status, reason, restart.delay);
+            if restart.escalate {
+                platform::notify(
+                    "Encoder Error",
+                    &format!(
+                        "ffmpeg stopped {} times in a row ({}): {}\n\nmoment keeps retrying. More in {}.",
+                        restart.failures,
+                        status,
+                        reason,
+                        diagnostics::LOG_PATH
+                    ),
+                );
+            }
+            if wait_for_restart(restart.delay, &rx, &device_state, &mut hotkeys) {
+                break 'main_loop;
+            }
+        }
     }
     Ok(())
 }
 
-fn save_final_clip() -> Result<(), Box<dyn std::error::Error>> {
-    beep(1000);
+// sleeps through the delay before an encoder restart, true if the user quit meanwhile
+fn wait_for_restart(delay: Duration, rx: &Receiver<bool>, device_state: &Option<DeviceState>, hotkeys: &mut Hotkeys) -> bool {
+    let until = Instant::now() + delay;
+    while Instant::now() < until {
+        if rx.try_recv().is_ok() {
+            return true;
+        }
+        // only quitting is handled while the encoder is down
+        if let Some(device_state) = device_state
+            && hotkeys.poll(device_state.get_keys()).contains(&Action::Quit)
+        {
+            return true;
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+    false
+}
 
-    let b0 = std::fs::metadata("buffer0.mp4");
-    let b1 = std::fs::metadata("buffer1.mp4");
-    let mut list = Vec::new();
+fn bindings(config: &Config) -> Vec<(Chord, Action)> {
+    let mut bindings: Vec<_> = config.saves.iter().map(|save| (save.key.clone(), Action::SaveClip(save.time))).collect();
+    bindings.push((config.quit_key.clone(), Action::Quit));
+    if let Some(pause_key) = &config.pause_key {
+        bindings.push((pause_key.clone(), Action::Pause));
+    }
+    bindings
+}
 
-    match (b0, b1) {
-        (Ok(m0), Ok(m1)) => {
-            if m0.modified()? < m1.modified()? {
-                list.push("buffer0.mp4");
-                list.push("buffer1.mp4");
-            } else {
-                list.push("buffer1.mp4");
-                list.push("buffer0.mp4");
-            }
+fn container(config: &Config) -> &'static str {
+    if config.container == "mkv" { "mkv" } else { "mp4" }
+}
+
+// Hotkeys, clip lengths and the container apply right away. Returns whether the encoder
+// has to be restarted for the rest. Sources are opened once at startup, so those keep
+// their old settings until moment is restarted.
+fn apply_config(config: &mut Config, mut new: Config, hotkeys: &mut Hotkeys, buffer: &Mutex<ReplayBuffer>, saver: &mut ClipSaver, report: bool) -> bool {
+    let needs_restart = new.capture != config.capture
+        || new.monitors != config.monitors
+        || new.synthetic_frames != config.synthetic_frames
+        || new.audio != config.audio
+        || new.ffmpeg != config.ffmpeg;
+    if needs_restart && report {
+        platform::notify("Configuration", "Capture, monitor, audio source and ffmpeg changes take effect after restarting moment.");
+    }
+    new.capture = std::mem::take(&mut config.capture);
+    new.monitors = std::mem::take(&mut config.monitors);
+    new.synthetic_frames = config.synthetic_frames;
+    new.audio = std::mem::take(&mut config.audio);
+    new.ffmpeg = std::mem::take(&mut config.ffmpeg);
+
+    hotkeys.rebind(bindings(&new));
+    buffer.lock().unwrap().set_window(new.time as u64 * 1000);
+    saver.set_container(container(&new));
+
+    let restart = new.fps != config.fps
+        || new.kbps != config.kbps
+        || new.encoder != config.encoder
+        || new.encoder_fallbacks != config.encoder_fallbacks
+        || new.crop != config.crop
+        || new.resolution != config.resolution
+        || new.audio_mode != config.audio_mode;
+    *config = new;
+    restart
+}
+
+// the monitors the config asks for, the main one when it asks for any that aren't there
+fn pick_monitors(config: &Config, monitors: &[capture::Monitor]) -> Vec<usize> {
+    let mut picked = Vec::new();
+    for choice in &config.monitors {
+        match choice.resolve(monitors) {
+            Ok(indexes) => picked.extend(indexes.into_iter().filter(|i| !picked.contains(i)).collect::<Vec<_>>()),
+            Err(e) => platform::notify("Configuration Error", &format!("\"monitor\" {}\n\nThat one is left out.", e)),
         }
-        (Ok(_), Err(_)) => list.push("buffer0.mp4"),
-        (Err(_), Ok(_)) => list.push("buffer1.mp4"),
-        _ => return Ok(()),
     }
+    if picked.is_empty() {
+        picked.push(0);
+    }
+    picked
+}
 
-    let list_path = "concat_list.txt";
-    {
-        let mut f = File::create(list_path)?;
-        for file in list {
-            writeln!(f, "file '{}'", file)?;
+// turns captured frames into what the encoder takes: cropped, then shrunk, then converted
+struct Pipeline {
+    crop: Option<Rect>,
+    scaler: Option<Scaler>,
+    converter: Converter,
+    encoder: EncoderSettings,
+    out_width: u32,
+    out_height: u32,
+}
+
+impl Pipeline {
+    fn new(config: &Config, available: &[String], screen_width: u32, screen_height: u32, pixel_format: PixelFormat) -> Self {
+        let crop = pick_crop(config, screen_width, screen_height);
+        let (width, height) = crop.map(|c| (c.width, c.height)).unwrap_or((screen_width, screen_height));
+        let (scaled_width, scaled_height) = config.resolution.resolve(width, height);
+        let scaler = scaler_for(width, height, scaled_width, scaled_height);
+        let (out_width, out_height) = convert::geometry(scaled_width, scaled_height);
+        let encoder = pick_encoder(config, available, out_width, out_height);
+        let converter = Converter::new(pixel_format, scaled_width, scaled_height, encoder.input_format());
+        Pipeline { crop, scaler, converter, encoder, out_width, out_height }
+    }
+
+    // `data` has to be a whole frame of the screen size this was made for
+    fn process(&mut self, data: Vec<u8>, screen_width: u32) -> Vec<u8> {
+        let data = match self.crop {
+            Some(crop) => crop.crop(&data, screen_width),
+            None => data,
+        };
+        let data = match &mut self.scaler {
+            Some(scaler) => scaler.scale(&data),
+            None => data,
+        };
+        self.converter.convert(&data)
+    }
+}
+
+// None for the whole screen, also when the configured crop doesn't fit on it
+fn pick_crop(config: &Config, width: u32, height: u32) -> Option<Rect> {
+    match config.crop.resolve(width, height) {
+        Ok(crop) => {
+            if let Some(c) = crop {
+                diagnostics::log("capture", &format!("cropping {}x{} at {},{} out of {}x{}", c.width, c.height, c.x, c.y, width, height));
+            }
+            crop
+        },
+        Err(e) => {
+            platform::notify("Configuration Error", &format!("\"crop\" {}\n\nThe whole screen is recorded instead.", e));
+            None
+        },
+    }
+}
+
+// None when the frames are recorded as captured
+fn scaler_for(width: u32, height: u32, scaled_width: u32, scaled_height: u32) -> Option<Scaler> {
+    if (scaled_width, scaled_height) == (width, height) {
+        return None;
+    }
+    diagnostics::log("capture", &format!("scaling {}x{} to {}x{}", width, height, scaled_width, scaled_height));
+    Some(Scaler::new((width, height), (scaled_width, scaled_height)))
+}
+
+// the configured encoder, then H.264 on the same hardware, then the fallback list
+fn pick_encoder(config: &Config, available: &[String], width: u32, height: u32) -> EncoderSettings {
+    let mut candidates = vec![config.encoder.clone()];
+    candidates.extend(config.encoder.h264_fallback());
+    candidates.extend(config.encoder_fallbacks.iter().cloned());
+
+    let test = |e: &EncoderSettings| encoder::test_encode(e, width, height, config.fps, config.kbps);
+    let (picked, skipped) = encoder::pick(candidates, available, test);
+    for reason in &skipped {
+        diagnostics::log("encoder", &format!("skipped {}", reason));
+    }
+    diagnostics::log("encoder", &format!("recording with {}", picked.name));
+    eprintln!("Recording with {}", picked.name);
+    if !skipped.is_empty() {
+        platform::notify(
+            "Encoder",
+            &format!("{}\n\nRecording with {} instead.", skipped.join("\n"), picked.name),
+        );
+    }
+    picked
+}
+
+// one titled track per source, or a single amix of all of them
+fn audio_mapping(audio: &[AudioConfig], mode: AudioMode) -> Vec<String> {
+    let mut args = Vec::new();
+    if audio.len() > 1 && mode == AudioMode::Mixed {
+        let inputs: String = (0..audio.len()).map(|i| format!("[0:a:{}]", i)).collect();
+        let title = audio.iter().map(|a| a.name.as_str()).collect::<Vec<_>>().join(" + ");
+        args.extend([
+            "-filter_complex".to_string(),
+            format!("{}amix=inputs={}:duration=longest:normalize=0[aout]", inputs, audio.len()),
+            "-map".to_string(),
+            "[aout]".to_string(),
+        ]);
+        args.extend(track_title(0, &title));
+    } else {
+        for (i, a) in audio.iter().enumerate() {
+            args.extend(["-map".to_string(), format!("0:a:{}", i)]);
+            args.extend(track_title(i, &a.name));
         }
     }
+    args
+}
 
-    beep(2000);
-
-    let now = OffsetDateTime::now_utc();
-    let fmt = format_description::parse("[year]-[month]-[day].[hour]_[minute]_[second]")?;
-    let output_name = format!("clip_{}.mp4", now.format(&fmt)?);
-
-    Command::new("./ffmpeg.exe")
-        .args([
-            "-y",
-            "-f",
-            "concat",
-            "-safe",
-            "0",
-            "-i",
-            list_path,
-            "-c",
-            "copy",
-            &output_name,
-        ])
-        .creation_flags(0x08000000)
-        .status()?;
-
-    let _ = std::fs::remove_file(list_path);
-    beep(3000);
-    Ok(())
+// mkv reads the title, players showing mp4 tracks go by the handler name
+fn track_title(index: usize, title: &str) -> [String; 4] {
+    [
+        format!("-metadata:s:a:{}", index),
+        format!("title={}", title),
+        format!("-metadata:s:a:{}", index),
+        format!("handler_name={}", title),
+    ]
 }
 
-fn beep(freq: u32) {
-    unsafe {
-        use windows::Win32::System::Diagnostics::Debug::Beep;
-        let _ = Beep(freq, 50);
+// snapshots right away and leaves the muxing to a background thread
+fn save_clip(buffer: &Mutex<ReplayBuffer>, saver: &mut ClipSaver, end: u64, seconds: i32) {
+    if let Some(snapshot) = buffer.lock().unwrap().snapshot(end, seconds as u64 * 1000) {
+        saver.save(snapshot, end);
     }
 }
@@ -0,0 +1,212 @@
+use std::io::{self, Write};
+
+// Just enough of a NUT muxer to hand ffmpeg raw frames and samples that carry their own
+// timestamps. Every frame gets a syncpoint in front of it, so one frame code with everything
+// spelled out is all it needs. See ffmpeg's doc/nut.texi for the format.
+
+const FILE_ID: &[u8] = b"nut/multimedia container\0";
+const MAIN_STARTCODE: u64 = 0x4E4D_7A56_1F5F_04AD;
+const STREAM_STARTCODE: u64 = 0x4E53_1140_5BF2_F9DB;
+const SYNCPOINT_STARTCODE: u64 = 0x4E4B_E4AD_EECA_4569;
+
+const VERSION: u64 = 3;
+const FLAG_KEY: u64 = 1;
+const FLAG_CODED_PTS: u64 = 8;
+const FLAG_STREAM_ID: u64 = 16;
+const FLAG_SIZE_MSB: u64 = 32;
+const FLAG_CHECKSUM: u64 = 64;
+// every frame code is the same, frame code 0 is the one written
+const FRAME_FLAGS: u64 = FLAG_KEY | FLAG_CODED_PTS | FLAG_STREAM_ID | FLAG_SIZE_MSB | FLAG_CHECKSUM;
+const MSB_PTS_SHIFT: u64 = 7;
+// the largest ffmpeg accepts, only the distance from a syncpoint to its frame header counts
+const MAX_DISTANCE: u64 = 65536;
+
+/// Timestamps are in microseconds for every stream.
+pub const TIME_BASE: u64 = 1_000_000;
+
+pub enum StreamKind {
+    Video { fourcc: [u8; 4], width: u32, height: u32 },
+    /// Interleaved s16le samples.
+    Audio { sample_rate: u32, channels: u16 },
+}
+
+pub struct NutWriter<W: Write> {
+    out: W,
+}
+
+impl<W: Write> NutWriter<W> {
+    /// Writes the headers, stream ids are indexes into `streams`.
+    pub fn new(mut out: W, streams: &[StreamKind]) -> io::Result<Self> {
+        out.write_all(FILE_ID)?;
+
+        let mut main = Vec::new();
+        put_v(&mut main, VERSION);
+        put_v(&mut main, streams.len() as u64);
+        put_v(&mut main, MAX_DISTANCE);
+        put_v(&mut main, 1); // time base count
+        put_v(&mut main, 1);
+        put_v(&mut main, TIME_BASE);
+        // one entry for all 255 frame codes ('N' is skipped): flags, 6 fields, pts delta,
+        // size multiplier, stream, size lsb, reserved count, code count
+        for field in [FRAME_FLAGS, 6, 0, 1, 0, 0, 0, 255] {
+            put_v(&mut main, field);
+        }
+        put_v(&mut main, 0); // header count - 1, no elision headers
+        write_packet(&mut out, MAIN_STARTCODE, &main)?;
+
+        for (id, stream) in streams.iter().enumerate() {
+            let mut header = Vec::new();
+            put_v(&mut header, id as u64);
+            let (class, fourcc) = match stream {
+                StreamKind::Video { fourcc, .. } => (0, *fourcc),
+                StreamKind::Audio { .. } => (1, *b"PSD\x10"),
+            };
+            put_v(&mut header, class);
+            put_v(&mut header, 4);
+            header.extend_from_slice(&fourcc);
+            put_v(&mut header, 0); // time base id
+            put_v(&mut header, MSB_PTS_SHIFT);
+            put_v(&mut header, TIME_BASE); // max pts distance
+            put_v(&mut header, 0); // decode delay
+            put_v(&mut header, 0); // stream flags
+            put_v(&mut header, 0); // codec specific data
+            match *stream {
+                StreamKind::Video { width, height, .. } => {
+                    put_v(&mut header, width as u64);
+                    put_v(&mut header, height as u64);
+                    put_v(&mut header, 0); // sample aspect ratio unknown
+                    put_v(&mut header, 0);
+                    put_v(&mut header, 0); // colorspace type
+                },
+                StreamKind::Audio { sample_rate, channels } => {
+                    put_v(&mut header, sample_rate as u64);
+                    put_v(&mut header, 1);
+                    put_v(&mut header, channels as u64);
+                },
+            }
+            write_packet(&mut out, STREAM_STARTCODE, &header)?;
+        }
+
+        Ok(NutWriter { out })
+    }
+
+    /// `pts` is in microseconds and must not go backwards within a stream.
+    pub fn write_frame(&mut self, stream: usize, pts: u64, data: &[u8]) -> io::Result<()> {
+        let mut syncpoint = Vec::new();
+        put_v(&mut syncpoint, pts); // global key pts, time base 0
+        put_v(&mut syncpoint, 0); // back pointer, nobody seeks in a pipe
+        write_packet(&mut self.out, SYNCPOINT_STARTCODE, &syncpoint)?;
+
+        let mut header = vec![0u8]; // frame code
+        put_v(&mut header, stream as u64);
+        // at or above 1 << MSB_PTS_SHIFT means a full timestamp instead of its low bits
+        put_v(&mut header, pts + (1 << MSB_PTS_SHIFT));
+        put_v(&mut header, data.len() as u64);
+        let checksum = crc32(&header);
+        header.extend_from_slice(&checksum.to_be_bytes());
+
+        self.out.write_all(&header)?;
+        self.out.write_all(data)
+    }
+}
+
+fn write_packet(out: &mut impl Write, startcode: u64, payload: &[u8]) -> io::Result<()> {
+    let forward_ptr = payload.len() as u64 + 4;
+    let mut header = startcode.to_be_bytes().to_vec();
+    put_v(&mut header, forward_ptr);
+    if forward_ptr > 4096 {
+        let checksum = crc32(&header);
+        header.extend_from_slice(&checksum.to_be_bytes());
+    }
+    out.write_all(&header)?;
+    out.write_all(payload)?;
+    out.write_all(&crc32(payload).to_be_bytes())
+}
+
+// 7 bits a byte, most significant first, the top bit set on all but the last
+fn put_v(buf: &mut Vec<u8>, value: u64) {
+    let groups = (64 - value.leading_zeros()).max(1).div_ceil(7);
+    for i in (1..groups).rev() {
+        buf.push(0x80 | ((value >> (7 * i)) & 0x7F) as u8);
+    }
+    buf.push((value & 0x7F) as u8);
+}
+
+// polynomial 0x04C11DB7, no reflection, starting from 0 and nothing xored at the end
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0u32;
+    for &byte in data {
+        crc ^= (byte as u32) << 24;
+        for _ in 0..8 {
+            crc = if crc & 0x8000_0000 != 0 { (crc << 1) ^ 0x04C1_1DB7 } else { crc << 1 };
+        }
+    }
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn v(value: u64) -> Vec<u8> {
+        let mut buf = Vec::new();
+        put_v(&mut buf, value);
+        buf
+    }
+
+    #[test]
+    fn put_v_known_values() {
+        assert_eq!(v(0), [0x00]);
+        assert_eq!(v(0x7F), [0x7F]);
+        assert_eq!(v(0x80), [0x81, 0x00]);
+        assert_eq!(v(0x3FFF), [0xFF, 0x7F]);
+        assert_eq!(v(0x4000), [0x81, 0x80, 0x00]);
+        assert_eq!(v(TIME_BASE), [0xBD, 0x84, 0x40]);
+        assert_eq!(v(u64::MAX), [0x81, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x7F]);
+    }
+
+    #[test]
+    fn crc32_known_values() {
+        assert_eq!(crc32(b""), 0);
+        // CRC-32/CKSUM's check value without its final xor
+        assert_eq!(crc32(b"123456789"), 0x765E_7680 ^ 0xFFFF_FFFF);
+    }
+
+    #[test]
+    fn packets_end_in_the_checksum_of_their_payload() {
+        let mut out = Vec::new();
+        write_packet(&mut out, SYNCPOINT_STARTCODE, b"payload").unwrap();
+        assert_eq!(out[..8], SYNCPOINT_STARTCODE.to_be_bytes());
+        assert_eq!(out[8], 7 + 4);
+        assert_eq!(out[9..16], *b"payload");
+        // with nothing xored at the end, data followed by its own checksum checks out to 0
+        assert_eq!(crc32(&out[9..]), 0);
+
+        // big packets check their header too
+        let mut out = Vec::new();
+        write_packet(&mut out, MAIN_STARTCODE, &[0; 5000]).unwrap();
+        let header_end = 8 + v(5004).len() + 4;
+        assert_eq!(crc32(&out[..header_end]), 0);
+        assert_eq!(crc32(&out[header_end..]), 0);
+    }
+
+    #[test]
+    fn frame_has_a_syncpoint_and_a_checked_header() {
+        let streams = [StreamKind::Video { fourcc: *b"NV12", width: 4, height: 2 }];
+        let mut writer = NutWriter::new(Vec::new(), &streams).unwrap();
+        let headers = writer.out.len();
+        assert!(writer.out.starts_with(FILE_ID));
+        writer.write_frame(0, 1_500_000, &[7; 12]).unwrap();
+        let frame = &writer.out[headers..];
+
+        let mut syncpoint = Vec::new();
+        write_packet(&mut syncpoint, SYNCPOINT_STARTCODE, &[v(1_500_000), v(0)].concat()).unwrap();
+        assert!(frame.starts_with(&syncpoint));
+
+        let header = &frame[syncpoint.len()..frame.len() - 12];
+        let expected = [vec![0], v(0), v(1_500_000 + 128), v(12)].concat();
+        assert_eq!(header[..header.len() - 4], expected);
+        assert_eq!(crc32(header), 0);
+        assert_eq!(frame[frame.len() - 12..], [7; 12]);
+    }
+}
@@ -0,0 +1,76 @@
+use device_query::DeviceState;
+use std::io::Write;
+use std::process::Command;
+use std::sync::mpsc::Sender;
+use std::time::Duration;
+use tray_icon::{
+    Icon, TrayIconBuilder,
+    menu::{Menu, MenuEvent, MenuItem},
+};
+
+// without an X display there is nothing to read keys from (headless runs)
+pub fn device_state() -> Option<DeviceState> {
+    DeviceState::checked_new()
+}
+
+// the scheduler on linux already sleeps with sub-millisecond precision
+pub fn begin_timer_period() {}
+
+pub fn ffmpeg() -> Command {
+    Command::new(crate::ffmpeg::path())
+}
+
+pub fn beep(_freq: u32) {
+    let mut stderr = std::io::stderr();
+    let _ = stderr.write_all(b"\x07");
+    let _ = stderr.flush();
+}
+
+pub fn show_info(title: &str, msg: &str) {
+    eprintln!("[{}] {}", title, msg);
+}
+
+pub fn show_error(title: &str, msg: &str) {
+    eprintln!("[{}] ERROR: {}", title, msg);
+}
+
+pub fn notify(title: &str, msg: &str) {
+    show_info(title, msg);
+}
+
+pub fn run_tray(tx: Sender<bool>) {
+    if gtk::init().is_err() {
+        // no display to put an icon on, LControl+F1 or a signal still ends the program
+        loop {
+            std::thread::park();
+        }
+    }
+
+    let tray_menu = Menu::new();
+    let quit_btn = MenuItem::new("Quit", true, None);
+    tray_menu.append(&quit_btn).unwrap();
+    let quit_id = quit_btn.into_id();
+
+    let icon_data = include_bytes!("../../icon.raw").to_vec();
+    let tray_icon_icon = Icon::from_rgba(icon_data, 16, 16).unwrap();
+    let _tray_icon = TrayIconBuilder::new()
+        .with_menu(Box::new(tray_menu))
+        .with_tooltip("acid's clipping kit")
+        .with_icon(tray_icon_icon)
+        .build()
+        .unwrap();
+
+    let menu_channel = MenuEvent::receiver();
+    gtk::glib::timeout_add_local(Duration::from_millis(50), move || {
+        if let Ok(event) = menu_channel.try_recv()
+            && event.id == quit_id
+        {
+            let _ = tx.send(true);
+            gtk::main_quit();
+            return gtk::glib::ControlFlow::Break;
+        }
+        gtk::glib::ControlFlow::Continue
+    });
+
+    gtk::main();
+}
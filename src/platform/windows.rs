@@ -0,0 +1,82 @@
+use device_query::DeviceState;
+use std::os::windows::process::CommandExt;
+use std::process::Command;
+use std::sync::mpsc::Sender;
+use tray_icon::{
+    Icon, TrayIconBuilder,
+    menu::{Menu, MenuEvent, MenuItem},
+};
+use win_msgbox::Okay;
+use winit::event_loop::{ControlFlow, EventLoop};
+
+const CREATE_NO_WINDOW: u32 = 0x08000000;
+
+pub fn device_state() -> Option<DeviceState> {
+    Some(DeviceState::new())
+}
+
+pub fn begin_timer_period() {
+    unsafe {
+        use windows::Win32::Media::timeBeginPeriod;
+        timeBeginPeriod(1);
+    }
+}
+
+pub fn ffmpeg() -> Command {
+    let mut cmd = Command::new(crate::ffmpeg::path());
+    cmd.creation_flags(CREATE_NO_WINDOW);
+    cmd
+}
+
+pub fn beep(freq: u32) {
+    unsafe {
+        use windows::Win32::System::Diagnostics::Debug::Beep;
+        let _ = Beep(freq, 50);
+    }
+}
+
+pub fn show_info(title: &str, msg: &str) {
+    let _ = win_msgbox::information::<Okay>(msg).title(title).show();
+}
+
+pub fn show_error(title: &str, msg: &str) {
+    let _ = win_msgbox::error::<Okay>(msg).title(title).show();
+}
+
+// like show_info but doesn't hold up the caller
+pub fn notify(title: &str, msg: &str) {
+    let (title, msg) = (title.to_string(), msg.to_string());
+    std::thread::spawn(move || show_info(&title, &msg));
+}
+
+pub fn run_tray(tx: Sender<bool>) {
+    let tray_menu = Menu::new();
+    let quit_btn = MenuItem::new("Quit", true, None);
+    tray_menu.append(&quit_btn).unwrap();
+    let quit_id = quit_btn.into_id();
+
+    let icon_data = include_bytes!("../../icon.raw").to_vec();
+    let tray_icon_icon = Icon::from_rgba(icon_data, 16, 16).unwrap();
+    let _tray_icon = TrayIconBuilder::new()
+        .with_menu(Box::new(tray_menu))
+        .with_tooltip("acid's clipping kit")
+        .with_icon(tray_icon_icon)
+        .build()
+        .unwrap();
+
+    let menu_channel = MenuEvent::receiver();
+    let event_loop = EventLoop::new().unwrap();
+
+    #[allow(deprecated)]
+    event_loop
+        .run(move |_event, event_loop| {
+            event_loop.set_control_flow(ControlFlow::Wait);
+            if let Ok(event) = menu_channel.try_recv() {
+                if event.id == quit_id {
+                    let _ = tx.send(true);
+                    event_loop.exit();
+                }
+            }
+        })
+        .unwrap();
+}
@@ -0,0 +1,372 @@
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+const EBML_HEADER: u32 = 0x1A45DFA3;
+const SEGMENT: u32 = 0x18538067;
+const TRACKS: u32 = 0x1654AE6B;
+const TRACK_ENTRY: u32 = 0xAE;
+const TRACK_NUMBER: u32 = 0xD7;
+const TRACK_TYPE: u32 = 0x83;
+const CLUSTER: u32 = 0x1F43B675;
+const CLUSTER_TIMESTAMP: u32 = 0xE7;
+const SIMPLE_BLOCK: u32 = 0xA3;
+
+const TRACK_TYPE_VIDEO: u64 = 1;
+
+struct Cluster {
+    // ffmpeg always writes a 1ms TimestampScale, so these are milliseconds
+    start: u64,
+    end: u64,
+    keyframe: bool,
+    data: Arc<[u8]>,
+}
+
+/// Shares the clusters with the buffer, so taking one is cheap enough to do between two frames.
+pub struct Snapshot {
+    head: Vec<u8>,
+    clusters: Vec<Arc<[u8]>>,
+    /// Timestamp of the first frame, in milliseconds.
+    pub start: u64,
+    /// Timestamp of the last frame, which can be before the `end` the snapshot was asked for.
+    pub end: u64,
+}
+
+impl Snapshot {
+    pub fn write_to(&self, w: &mut impl Write) -> io::Result<()> {
+        w.write_all(&self.head)?;
+        for cluster in &self.clusters {
+            w.write_all(cluster)?;
+        }
+        Ok(())
+    }
+}
+
+/// The last `window_ms` of encoder output, kept in RAM as whole Matroska clusters.
+/// ffmpeg opens a new cluster on every video keyframe, which makes each keyframe
+/// cluster a valid place for a clip to start when remuxing with `-c copy`.
+/// Clusters are also cut every 100ms (`-cluster_time_limit`) so the buffer trails the encoder by little.
+pub struct ReplayBuffer {
+    window_ms: u64,
+    head: Vec<u8>,
+    clusters: VecDeque<Cluster>,
+}
+
+impl ReplayBuffer {
+    pub fn new(window_ms: u64) -> Self {
+        ReplayBuffer { window_ms, head: Vec::new(), clusters: VecDeque::new() }
+    }
+
+    pub fn set_window(&mut self, window_ms: u64) {
+        self.window_ms = window_ms;
+    }
+
+    fn push(&mut self, cluster: Cluster) {
+        self.clusters.push_back(cluster);
+        if let Some(start) = self.start_index(self.window_ms) {
+            self.clusters.drain(..start);
+        }
+    }
+
+    /// Timestamp of the last frame that made it into the buffer.
+    pub fn newest(&self) -> u64 {
+        self.clusters.back().map(|c| c.end).unwrap_or(0)
+    }
+
+    // last keyframe cluster at or before `cutoff`, or the oldest one if the buffer doesn't reach back that far
+    fn keyframe_before(&self, cutoff: u64) -> Option<usize> {
+        self.clusters.iter().rposition(|c| c.keyframe && c.start <= cutoff).or_else(|| self.clusters.iter().position(|c| c.keyframe))
+    }
+
+    fn start_index(&self, ms: u64) -> Option<usize> {
+        self.keyframe_before(self.newest().saturating_sub(ms))
+    }
+
+    /// A standalone Matroska stream starting on the keyframe closest before `end - ms`.
+    /// Anything after `end` is left in, the caller trims it with the returned start time.
+    pub fn snapshot(&self, end: u64, ms: u64) -> Option<Snapshot> {
+        let start = self.keyframe_before(end.saturating_sub(ms))?;
+        Some(Snapshot {
+            head: self.head.clone(),
+            clusters: self.clusters.range(start..).map(|c| c.data.clone()).collect(),
+            start: self.clusters[start].start,
+            end: self.newest(),
+        })
+    }
+
+    pub fn clear(&mut self) {
+        self.head.clear();
+        self.clusters.clear();
+    }
+}
+
+/// Reads ffmpeg's Matroska output from `stdout` into `buffer` until the stream ends.
+pub fn spawn_reader(stdout: impl Read + Send + 'static, buffer: Arc<Mutex<ReplayBuffer>>) -> JoinHandle<io::Result<()>> {
+    thread::spawn(move || read_stream(stdout, &buffer))
+}
+
+fn read_stream(mut r: impl Read, buffer: &Mutex<ReplayBuffer>) -> io::Result<()> {
+    buffer.lock().unwrap().clear();
+    let mut video_track = None;
+    let mut started = false;
+
+    loop {
+        let Header { id, size, raw: header } = match read_header(&mut r)? {
+            Some(h) => h,
+            None => return Ok(()),
+        };
+
+        if id == SEGMENT {
+            // children follow inline, the segment size is unknown on a pipe anyway
+            buffer.lock().unwrap().head.extend_from_slice(&header);
+            continue;
+        }
+
+        let size = size.ok_or_else(|| invalid(format!("Unknown size for element {:X}", id)))?;
+        let mut body = vec![0u8; size as usize];
+        r.read_exact(&mut body)?;
+
+        if id == CLUSTER {
+            started = true;
+            let mut data = header;
+            data.extend_from_slice(&body);
+            let cluster = parse_cluster(&body, video_track, data.into())?;
+            buffer.lock().unwrap().push(cluster);
+        } else if !started || id == EBML_HEADER {
+            if id == TRACKS {
+                video_track = find_video_track(&body)?;
+            }
+            let mut buffer = buffer.lock().unwrap();
+            buffer.head.extend_from_slice(&header);
+            buffer.head.extend_from_slice(&body);
+        }
+    }
+}
+
+fn parse_cluster(body: &[u8], video_track: Option<u64>, data: Arc<[u8]>) -> io::Result<Cluster> {
+    let mut start = 0;
+    let mut end = 0;
+    let mut keyframe = None;
+
+    for (id, payload) in children(body)? {
+        match id {
+            CLUSTER_TIMESTAMP => {
+                start = read_uint(payload);
+                end = end.max(start);
+            },
+            SIMPLE_BLOCK => {
+                let (track, len) = read_vint(payload).ok_or_else(|| invalid("Bad block track number".into()))?;
+                if payload.len() < len + 3 {
+                    return Err(invalid("Truncated block".into()));
+                }
+                let rel = i16::from_be_bytes([payload[len], payload[len + 1]]);
+                end = end.max(start.saturating_add_signed(rel as i64));
+                if keyframe.is_none() && Some(track) == video_track {
+                    keyframe = Some(payload[len + 2] & 0x80 != 0);
+                }
+            },
+            _ => {},
+        }
+    }
+
+    // without a known video track every cluster counts as a starting point
+    Ok(Cluster { start, end, keyframe: keyframe.unwrap_or(video_track.is_none()), data })
+}
+
+fn find_video_track(tracks: &[u8]) -> io::Result<Option<u64>> {
+    for (id, entry) in children(tracks)? {
+        if id != TRACK_ENTRY {
+            continue;
+        }
+        let mut number = None;
+        let mut kind = None;
+        for (id, payload) in children(entry)? {
+            match id {
+                TRACK_NUMBER => number = Some(read_uint(payload)),
+                TRACK_TYPE => kind = Some(read_uint(payload)),
+                _ => {},
+            }
+        }
+        if kind == Some(TRACK_TYPE_VIDEO) {
+            return Ok(number);
+        }
+    }
+    Ok(None)
+}
+
+fn children(mut body: &[u8]) -> io::Result<Vec<(u32, &[u8])>> {
+    let mut out = Vec::new();
+    while !body.is_empty() {
+        let (id, id_len) = read_id(body).ok_or_else(|| invalid("Bad element id".into()))?;
+        let (size, size_len) = read_vint(&body[id_len..]).ok_or_else(|| invalid("Bad element size".into()))?;
+        let start = id_len + size_len;
+        let end = start + size as usize;
+        if end > body.len() {
+            return Err(invalid(format!("Element {:X} overruns its parent", id)));
+        }
+        out.push((id, &body[start..end]));
+        body = &body[end..];
+    }
+    Ok(out)
+}
+
+struct Header {
+    id: u32,
+    // None when unknown, which ffmpeg writes for the segment on a pipe
+    size: Option<u64>,
+    // the id and size as they were read
+    raw: Vec<u8>,
+}
+
+// None at a clean end of stream
+fn read_header(r: &mut impl Read) -> io::Result<Option<Header>> {
+    let mut first = [0u8; 1];
+    if r.read(&mut first)? == 0 {
+        return Ok(None);
+    }
+    let mut header = vec![first[0]];
+    let id_len = vint_len(first[0]).filter(|&l| l <= 4).ok_or_else(|| invalid("Bad element id".into()))?;
+    read_more(r, &mut header, id_len - 1)?;
+
+    let size_start = header.len();
+    read_more(r, &mut header, 1)?;
+    let size_len = vint_len(header[size_start]).ok_or_else(|| invalid("Bad element size".into()))?;
+    read_more(r, &mut header, size_len - 1)?;
+
+    let (id, _) = read_id(&header).unwrap();
+    let size = &header[size_start..];
+    let unknown = size[0] == (0xFF >> (size_len - 1)) as u8 && size[1..].iter().all(|&b| b == 0xFF);
+    let size = if unknown { None } else { read_vint(size).map(|(v, _)| v) };
+    Ok(Some(Header { id, size, raw: header }))
+}
+
+fn read_more(r: &mut impl Read, buf: &mut Vec<u8>, n: usize) -> io::Result<()> {
+    let start = buf.len();
+    buf.resize(start + n, 0);
+    r.read_exact(&mut buf[start..])
+}
+
+fn vint_len(first: u8) -> Option<usize> {
+    match first.leading_zeros() {
+        n if n < 8 => Some(n as usize + 1),
+        _ => None,
+    }
+}
+
+fn read_id(data: &[u8]) -> Option<(u32, usize)> {
+    let len = vint_len(*data.first()?)?;
+    let bytes = data.get(..len)?;
+    Some((bytes.iter().fold(0u32, |acc, &b| (acc << 8) | b as u32), len))
+}
+
+fn read_vint(data: &[u8]) -> Option<(u64, usize)> {
+    let len = vint_len(*data.first()?)?;
+    let bytes = data.get(..len)?;
+    let first = (bytes[0] as u64) & (0xFF >> len);
+    Some((bytes[1..].iter().fold(first, |acc, &b| (acc << 8) | b as u64), len))
+}
+
+fn read_uint(data: &[u8]) -> u64 {
+    data.iter().fold(0u64, |acc, &b| (acc << 8) | b as u64)
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // an element with a one byte size, which is all these small ones need
+    fn element(id: u32, body: &[u8]) -> Vec<u8> {
+        let mut out: Vec<u8> = id.to_be_bytes().into_iter().skip_while(|&b| b == 0).collect();
+        out.push(0x80 | body.len() as u8);
+        out.extend_from_slice(body);
+        out
+    }
+
+    fn block(track: u8, time: i16, key: bool, data: &[u8]) -> Vec<u8> {
+        let mut body = vec![0x80 | track];
+        body.extend_from_slice(&time.to_be_bytes());
+        body.push(if key { 0x80 } else { 0 });
+        body.extend_from_slice(data);
+        element(SIMPLE_BLOCK, &body)
+    }
+
+    fn cluster(start: u16, blocks: &[Vec<u8>]) -> Vec<u8> {
+        let mut body = element(CLUSTER_TIMESTAMP, &start.to_be_bytes());
+        body.extend(blocks.concat());
+        element(CLUSTER, &body)
+    }
+
+    // video on track 1, audio on track 2
+    fn tracks() -> Vec<u8> {
+        let video = [element(TRACK_NUMBER, &[1]), element(TRACK_TYPE, &[1])].concat();
+        let audio = [element(TRACK_NUMBER, &[2]), element(TRACK_TYPE, &[2])].concat();
+        element(TRACKS, &[element(TRACK_ENTRY, &video), element(TRACK_ENTRY, &audio)].concat())
+    }
+
+    fn parse(cluster: &[u8], video_track: Option<u64>) -> Cluster {
+        let body = children(cluster).unwrap()[0].1;
+        parse_cluster(body, video_track, cluster.into()).unwrap()
+    }
+
+    #[test]
+    fn cluster_times_cover_every_block() {
+        let c = parse(&cluster(1000, &[block(1, 0, true, b"v"), block(2, 20, true, b"a"), block(1, 16, false, b"v")]), Some(1));
+        assert_eq!((c.start, c.end), (1000, 1020));
+    }
+
+    #[test]
+    fn keyframe_comes_from_the_first_video_block() {
+        // audio blocks are always flagged as keyframes, they must not count
+        let c = parse(&cluster(0, &[block(2, 0, true, b"a"), block(1, 5, false, b"v")]), Some(1));
+        assert!(!c.keyframe);
+        let c = parse(&cluster(0, &[block(2, 0, false, b"a"), block(1, 5, true, b"v"), block(1, 21, false, b"v")]), Some(1));
+        assert!(c.keyframe);
+        // without a video track to go by, any cluster is a place to start
+        assert!(parse(&cluster(0, &[block(2, 0, false, b"a")]), None).keyframe);
+    }
+
+    #[test]
+    fn finds_the_video_track() {
+        let tracks = tracks();
+        assert_eq!(find_video_track(children(&tracks).unwrap()[0].1).unwrap(), Some(1));
+    }
+
+    #[test]
+    fn truncated_block_is_an_error() {
+        let cluster = element(CLUSTER, &element(SIMPLE_BLOCK, &[0x81, 0]));
+        assert!(parse_cluster(children(&cluster).unwrap()[0].1, Some(1), cluster.as_slice().into()).is_err());
+    }
+
+    #[test]
+    fn snapshot_starts_on_a_keyframe() {
+        let clusters = [
+            cluster(0, &[block(1, 0, true, b"k0")]),
+            cluster(100, &[block(1, 0, false, b"p1")]),
+            cluster(200, &[block(1, 0, true, b"k2")]),
+            cluster(300, &[block(1, 0, false, b"p3")]),
+        ];
+        let head = element(EBML_HEADER, b"head");
+        let segment = [0x18, 0x53, 0x80, 0x67, 0x01, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF];
+        let stream = [head.clone(), segment.to_vec(), tracks(), clusters.concat()].concat();
+
+        let buffer = Mutex::new(ReplayBuffer::new(10_000));
+        read_stream(stream.as_slice(), &buffer).unwrap();
+        let buffer = buffer.lock().unwrap();
+        assert_eq!(buffer.newest(), 300);
+
+        // 150ms back from 300 falls before the keyframe at 200, so the clip reaches back to the one at 0
+        let snapshot = buffer.snapshot(300, 150).unwrap();
+        assert_eq!(snapshot.start, 0);
+        let snapshot = buffer.snapshot(300, 100).unwrap();
+        assert_eq!((snapshot.start, snapshot.end), (200, 300));
+
+        let mut out = Vec::new();
+        snapshot.write_to(&mut out).unwrap();
+        assert_eq!(out, [head, segment.to_vec(), tracks(), clusters[2].clone(), clusters[3].clone()].concat());
+    }
+}
@@ -0,0 +1,138 @@
+use crate::simd;
+
+// weights of one output pixel add up to 1 << WEIGHT_BITS. Eight bits let the row pass add up
+// bytes in 16 bits, which is twice the lanes of 32, and its sums keep those eight bits below the
+// byte so rounding happens only once at the end.
+const WEIGHT_BITS: u32 = 8;
+
+/// Shrinks BGRA frames by averaging the area each output pixel covers, which keeps text and thin
+/// lines readable where skipping pixels would shimmer. Rows are combined first, whole rows at a
+/// time so the compiler vectorizes it, which leaves the column pass only the output rows to do.
+pub struct Scaler {
+    from: (usize, usize),
+    to: (usize, usize),
+    rows: Vec<Span>,
+    // where each output column starts in the source and its weights, `taps` of them each,
+    // padded with zeros so the inner loop always runs the same count
+    column_starts: Vec<usize>,
+    column_weights: Vec<u16>,
+    taps: usize,
+    // one output row before its columns are combined, with room for the padding taps to read
+    tall: Vec<u16>,
+}
+
+// the source pixels one output pixel covers and how much of each
+struct Span {
+    start: usize,
+    weights: Vec<u16>,
+}
+
+impl Scaler {
+    /// Only shrinks, `to` must not be larger than `from` either way.
+    pub fn new(from: (u32, u32), to: (u32, u32)) -> Self {
+        assert!(to.0 <= from.0 && to.1 <= from.1 && to.0 > 0 && to.1 > 0, "can only shrink");
+        let from = (from.0 as usize, from.1 as usize);
+        let to = (to.0 as usize, to.1 as usize);
+        let columns = spans(from.0, to.0);
+        let taps = columns.iter().map(|s| s.weights.len()).max().unwrap_or(1);
+        let column_weights = columns.iter().flat_map(|s| s.weights.iter().copied().chain(std::iter::repeat(0)).take(taps)).collect();
+        Scaler {
+            from,
+            to,
+            rows: spans(from.1, to.1),
+            column_starts: columns.iter().map(|s| s.start * 4).collect(),
+            column_weights,
+            taps,
+            tall: vec![0; (from.0 + taps) * 4],
+        }
+    }
+
+    pub fn scale(&mut self, bgra: &[u8]) -> Vec<u8> {
+        let mut out = vec![0u8; self.to.0 * 4 * self.to.1];
+        simd::wide(#[inline(always)] || self.scale_into(bgra, &mut out));
+        out
+    }
+
+    #[inline(always)]
+    fn scale_into(&mut self, bgra: &[u8], out: &mut [u8]) {
+        let (from_width, to_width) = (self.from.0 * 4, self.to.0 * 4);
+
+        for (span, out) in self.rows.iter().zip(out.chunks_exact_mut(to_width)) {
+            let tall = &mut self.tall[..from_width];
+            tall.fill(0);
+            for (row, &weight) in bgra[span.start * from_width..].chunks_exact(from_width).zip(&span.weights) {
+                for (tall, &value) in tall.iter_mut().zip(row) {
+                    *tall += value as u16 * weight;
+                }
+            }
+
+            // the common shrinks need few taps, a known count lets the compiler unroll them
+            let (starts, weights, tall) = (&self.column_starts, &self.column_weights, &self.tall);
+            match self.taps {
+                1 => columns::<1>(starts, weights, tall, out),
+                2 => columns::<2>(starts, weights, tall, out),
+                3 => columns::<3>(starts, weights, tall, out),
+                4 => columns::<4>(starts, weights, tall, out),
+                taps => columns_any(starts, weights, taps, tall, out),
+            }
+        }
+    }
+}
+
+#[inline(always)]
+fn columns<const TAPS: usize>(starts: &[usize], weights: &[u16], tall: &[u16], out: &mut [u8]) {
+    for ((&start, weights), out) in starts.iter().zip(weights.chunks_exact(TAPS)).zip(out.chunks_exact_mut(4)) {
+        let weights: &[u16; TAPS] = weights.try_into().unwrap();
+        let pixels = &tall[start..start + TAPS * 4];
+        let mut sum = [0u32; 4];
+        for (t, &weight) in weights.iter().enumerate() {
+            for c in 0..4 {
+                sum[c] += pixels[t * 4 + c] as u32 * weight as u32;
+            }
+        }
+        store(sum, out);
+    }
+}
+
+fn columns_any(starts: &[usize], weights: &[u16], taps: usize, tall: &[u16], out: &mut [u8]) {
+    for ((&start, weights), out) in starts.iter().zip(weights.chunks_exact(taps)).zip(out.chunks_exact_mut(4)) {
+        let mut sum = [0u32; 4];
+        for (pixel, &weight) in tall[start..].chunks_exact(4).zip(weights) {
+            for c in 0..4 {
+                sum[c] += pixel[c] as u32 * weight as u32;
+            }
+        }
+        store(sum, out);
+    }
+}
+
+#[inline(always)]
+fn store(sum: [u32; 4], out: &mut [u8]) {
+    for c in 0..4 {
+        out[c] = ((sum[c] + (1 << (2 * WEIGHT_BITS - 1))) >> (2 * WEIGHT_BITS)) as u8;
+    }
+}
+
+// output pixel i covers [i * from / to, (i + 1) * from / to) of the source, pixels cut by
+// either edge count for the part inside
+fn spans(from: usize, to: usize) -> Vec<Span> {
+    (0..to)
+        .map(|i| {
+            // in units of 1/to of a source pixel, so everything stays whole
+            let (begin, end) = (i * from, (i + 1) * from);
+            let start = begin / to;
+            let mut weights: Vec<u16> = (start..end.div_ceil(to))
+                .map(|p| {
+                    let covered = end.min((p + 1) * to) - begin.max(p * to);
+                    ((covered << WEIGHT_BITS) / from) as u16
+                })
+                .collect();
+            // whatever rounding lost goes to the biggest one, so a flat colour stays exactly that colour
+            let lost = (1 << WEIGHT_BITS) - weights.iter().sum::<u16>();
+            if let Some(max) = weights.iter_mut().max() {
+                *max += lost;
+            }
+            Span { start, weights }
+        })
+        .collect()
+}
@@ -0,0 +1,18 @@
+/// Runs `f` from a copy built for AVX2 when the cpu has it. Only code inlined into that copy gets
+/// the wider registers, so `f` has to be an `#[inline(always)]` closure calling `#[inline(always)]`
+/// functions.
+#[inline(always)]
+pub fn wide<R>(f: impl FnOnce() -> R) -> R {
+    #[cfg(target_arch = "x86_64")]
+    if is_x86_feature_detected!("avx2") {
+        // SAFETY: the cpu was just checked for avx2
+        return unsafe { avx2(f) };
+    }
+    f()
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2")]
+fn avx2<R>(f: impl FnOnce() -> R) -> R {
+    f()
+}
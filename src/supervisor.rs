@@ -0,0 +1,46 @@
+use std::time::{Duration, Instant};
+
+const FIRST_DELAY: Duration = Duration::from_secs(1);
+const MAX_DELAY: Duration = Duration::from_secs(30);
+// an encoder that ran this long before dying counts as a fresh start, not another failure in a row
+const STABLE: Duration = Duration::from_secs(60);
+const ESCALATE_AFTER: u32 = 3;
+
+/// Keeps track of encoder crashes to decide how long to wait before restarting it
+/// and when the user should hear about it.
+pub struct Supervisor {
+    failures: u32,
+    started: Instant,
+    escalated: bool,
+}
+
+pub struct Restart {
+    pub delay: Duration,
+    /// Crashes in a row so far, this one included.
+    pub failures: u32,
+    /// Set once per streak, when it gets long enough to be worth a popup.
+    pub escalate: bool,
+}
+
+impl Supervisor {
+    pub fn new() -> Self {
+        Supervisor { failures: 0, started: Instant::now(), escalated: false }
+    }
+
+    pub fn started(&mut self) {
+        self.started = Instant::now();
+    }
+
+    pub fn crashed(&mut self) -> Restart {
+        if self.started.elapsed() >= STABLE {
+            self.failures = 0;
+            self.escalated = false;
+        }
+        self.failures += 1;
+
+        let delay = FIRST_DELAY.saturating_mul(1 << (self.failures - 1).min(5)).min(MAX_DELAY);
+        let escalate = self.failures >= ESCALATE_AFTER && !self.escalated;
+        self.escalated |= escalate;
+        Restart { delay, failures: self.failures, escalate }
+    }
+}
@@ -0,0 +1,160 @@
+use crate::nut::{NutWriter, StreamKind, TIME_BASE};
+use std::io;
+use std::process::ChildStdin;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// Frames that may wait for ffmpeg. Each is a whole raw picture, so this is kept small.
+const QUEUE_FRAMES: usize = 4;
+
+/// The video stream, audio inputs follow it in the order they were configured.
+pub const VIDEO_STREAM: usize = 0;
+
+/// Counted over the whole run, across encoder restarts.
+#[derive(Default)]
+pub struct FrameStats {
+    pub captured: AtomicU64,
+    pub written: AtomicU64,
+    /// Frames thrown away because ffmpeg fell behind.
+    pub dropped: AtomicU64,
+    /// Frames sent again to keep keyframes coming while the screen sits still.
+    pub duplicated: AtomicU64,
+}
+
+impl FrameStats {
+    pub fn summary(&self) -> String {
+        format!(
+            "captured {}, written {}, dropped {}, duplicated {}",
+            self.captured.load(Ordering::Relaxed),
+            self.written.load(Ordering::Relaxed),
+            self.dropped.load(Ordering::Relaxed),
+            self.duplicated.load(Ordering::Relaxed)
+        )
+    }
+}
+
+/// The timeline of one encoder session, shared by video and audio so both are stamped alike.
+/// Paused stretches are left out of it, a clip just skips them.
+pub struct MediaClock {
+    epoch: Instant,
+    paused: Mutex<(Option<Instant>, Duration)>,
+}
+
+impl MediaClock {
+    pub fn new(paused: bool) -> Self {
+        let now = Instant::now();
+        MediaClock { epoch: now, paused: Mutex::new((paused.then_some(now), Duration::ZERO)) }
+    }
+
+    /// Microseconds of unpaused time between the start of the session and `at`.
+    pub fn at(&self, at: Instant) -> u64 {
+        let (since, total) = *self.paused.lock().unwrap();
+        let paused = total + since.map(|s| at.saturating_duration_since(s)).unwrap_or_default();
+        let elapsed = at.saturating_duration_since(self.epoch).saturating_sub(paused);
+        (elapsed.as_nanos() * TIME_BASE as u128 / 1_000_000_000) as u64
+    }
+
+    pub fn now(&self) -> u64 {
+        self.at(Instant::now())
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.lock().unwrap().0.is_some()
+    }
+
+    pub fn set_paused(&self, paused: bool) {
+        let mut state = self.paused.lock().unwrap();
+        match (state.0, paused) {
+            (None, true) => state.0 = Some(Instant::now()),
+            (Some(since), false) => *state = (None, state.1 + since.elapsed()),
+            _ => {},
+        }
+    }
+}
+
+enum Message {
+    Frame { stream: usize, pts: u64, data: Arc<Vec<u8>> },
+    End,
+}
+
+pub enum Sent {
+    Queued,
+    /// The queue was full and the frame was dropped.
+    Dropped,
+    /// The writer stopped because ffmpeg no longer takes input.
+    Closed,
+}
+
+/// A handle audio inputs use to add their samples to the same stream as the frames.
+#[derive(Clone)]
+pub struct FrameSender {
+    tx: Sender<Message>,
+    queued_video: Arc<AtomicUsize>,
+}
+
+impl FrameSender {
+    /// Audio is never dropped, there is little of it and a gap would be heard. False once the
+    /// writer has stopped.
+    pub fn send_audio(&self, stream: usize, pts: u64, samples: Vec<u8>) -> bool {
+        self.tx.send(Message::Frame { stream, pts, data: Arc::new(samples) }).is_ok()
+    }
+}
+
+/// Muxes frames and audio into NUT on ffmpeg's stdin from its own thread, so a stalled
+/// encoder never holds up capture. Every frame carries the time it was captured.
+pub struct FrameWriter {
+    sender: FrameSender,
+    writer: JoinHandle<io::Result<()>>,
+}
+
+impl FrameWriter {
+    pub fn start(stdin: ChildStdin, streams: Vec<StreamKind>, stats: Arc<FrameStats>) -> Self {
+        let (tx, rx) = mpsc::channel::<Message>();
+        let queued_video = Arc::new(AtomicUsize::new(0));
+        let sender = FrameSender { tx, queued_video: queued_video.clone() };
+
+        let writer = thread::spawn(move || {
+            let mut nut = NutWriter::new(stdin, &streams)?;
+            for message in rx {
+                let Message::Frame { stream, pts, data } = message else {
+                    break;
+                };
+                nut.write_frame(stream, pts, &data)?;
+                if stream == VIDEO_STREAM {
+                    queued_video.fetch_sub(1, Ordering::Relaxed);
+                    stats.written.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+            Ok(())
+        });
+        FrameWriter { sender, writer }
+    }
+
+    pub fn sender(&self) -> FrameSender {
+        self.sender.clone()
+    }
+
+    /// Never blocks. When the queue is full the newest frame is the one dropped, so what
+    /// reaches ffmpeg is always in order and only ever missing whole frames.
+    /// Frames are shared so a repeated one isn't copied.
+    pub fn send(&self, frame: Arc<Vec<u8>>, pts: u64) -> Sent {
+        if self.sender.queued_video.load(Ordering::Relaxed) >= QUEUE_FRAMES {
+            return Sent::Dropped;
+        }
+        self.sender.queued_video.fetch_add(1, Ordering::Relaxed);
+        match self.sender.tx.send(Message::Frame { stream: VIDEO_STREAM, pts, data: frame }) {
+            Ok(()) => Sent::Queued,
+            Err(_) => Sent::Closed,
+        }
+    }
+
+    /// Lets the queue drain and closes stdin, which tells ffmpeg the input is over. Audio
+    /// inputs may still hold a sender, so the end is sent rather than waited for.
+    pub fn finish(self) -> io::Result<()> {
+        let _ = self.sender.tx.send(Message::End);
+        self.writer.join().unwrap_or(Ok(()))
+    }
+}